@@ -0,0 +1,336 @@
+use super::*;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header;
+use anchor_client::solana_sdk::hash::hashv;
+use base58::ToBase58;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Header an api key can be sent in, `Authorization: Bearer <key>` works too.
+pub const API_KEY_HEADER: &str = "x-api-key";
+const API_KEY_PREFIX: &str = "shop_";
+/// Probed by load balancers and orchestrators, which have no key.
+const PUBLIC_PATH_PREFIX: &str = "/health/";
+
+tokio::task_local! {
+    static API_KEY_LABEL: String;
+}
+
+/// Label of the api key the current request was authenticated with, set by
+/// `ApiKeyAuth` for the whole request.
+pub fn current_api_key_label() -> Option<String> {
+    API_KEY_LABEL.try_with(|label| label.clone()).ok()
+}
+
+/// Api key as stored in the api keys file, the key itself is never stored.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKeyRecord {
+    pub id: String,
+    pub label: String,
+    pub key_hash: String,
+    /// unix timestamps
+    pub created_at: u64,
+    #[serde(default)]
+    pub revoked_at: Option<u64>,
+}
+
+pub fn hash_api_key(api_key: &str) -> String {
+    hashv(&[api_key.as_bytes()]).to_string()
+}
+
+pub fn load_api_keys(api_keys_file: &str) -> ShopResult<Vec<ApiKeyRecord>> {
+    if !Path::new(api_keys_file).exists() {
+        return Ok(Vec::new());
+    }
+    let contents = std::fs::read_to_string(api_keys_file)?;
+    let api_keys = serde_json::from_str(&contents).map_err(|e| {
+        errors::ShopCustomError(format!("api keys file {api_keys_file} is corrupted: {e}"))
+    })?;
+    Ok(api_keys)
+}
+
+fn save_api_keys(api_keys_file: &str, api_keys: &[ApiKeyRecord]) -> ShopResult<()> {
+    let contents = serde_json::to_string_pretty(api_keys)?;
+    configure::write_private_file(api_keys_file, &contents)
+}
+
+/// Creates an api key and stores its hash, the key is only ever returned here.
+pub fn create_api_key(api_keys_file: &str, label: &str) -> ShopResult<(ApiKeyRecord, String)> {
+    let label = label.trim();
+    if label.is_empty() {
+        return Err(Box::new(errors::ShopCustomError(
+            "an api key needs a label".to_string(),
+        )));
+    }
+    let mut api_keys = load_api_keys(api_keys_file)?;
+    if api_keys
+        .iter()
+        .any(|api_key| api_key.revoked_at.is_none() && api_key.label == label)
+    {
+        return Err(Box::new(errors::ShopCustomError(format!(
+            "an active api key is already labelled {label}"
+        ))));
+    }
+
+    // the secret half of a fresh keypair, 32 bytes from the os random generator
+    let api_key = format!(
+        "{API_KEY_PREFIX}{}",
+        Keypair::new().to_bytes()[..32].to_base58()
+    );
+    let key_hash = hash_api_key(&api_key);
+    let api_key_record = ApiKeyRecord {
+        id: key_hash[..8].to_string(),
+        label: label.to_string(),
+        key_hash,
+        created_at: unix_timestamp(),
+        revoked_at: None,
+    };
+    api_keys.push(api_key_record.clone());
+    save_api_keys(api_keys_file, &api_keys)?;
+    Ok((api_key_record, api_key))
+}
+
+/// Revoked keys are kept in the file so `keys list` still shows them.
+pub fn revoke_api_key(api_keys_file: &str, id: &str) -> ShopResult<ApiKeyRecord> {
+    let mut api_keys = load_api_keys(api_keys_file)?;
+    let api_key = api_keys
+        .iter_mut()
+        .find(|api_key| api_key.id == id && api_key.revoked_at.is_none())
+        .ok_or_else(|| errors::ShopCustomError(format!("no active api key with id {id}")))?;
+    api_key.revoked_at = Some(unix_timestamp());
+    let api_key_record = api_key.clone();
+    save_api_keys(api_keys_file, &api_keys)?;
+    Ok(api_key_record)
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Runs `keys create <label>`, `keys list` or `keys revoke <id>`.
+pub fn run_api_keys_command(args: &[String]) -> std::io::Result<()> {
+    let to_io_error =
+        |e: Box<dyn Error>| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string());
+    let api_keys_file =
+        configure::setup_environment_and_get_api_keys_file().map_err(to_io_error)?;
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["create", label] => {
+            let (api_key_record, api_key) =
+                create_api_key(&api_keys_file, label).map_err(to_io_error)?;
+            println!(
+                "created api key {} labelled {}, it is not shown again:",
+                api_key_record.id, api_key_record.label
+            );
+            println!("{api_key}");
+        }
+        ["list"] => {
+            for api_key_record in load_api_keys(&api_keys_file).map_err(to_io_error)? {
+                let status = match api_key_record.revoked_at {
+                    Some(revoked_at) => format!("revoked at {revoked_at}"),
+                    None => "active".to_string(),
+                };
+                println!(
+                    "{}\t{}\tcreated at {}\t{status}",
+                    api_key_record.id, api_key_record.label, api_key_record.created_at
+                );
+            }
+        }
+        ["revoke", id] => {
+            let api_key_record = revoke_api_key(&api_keys_file, id).map_err(to_io_error)?;
+            println!(
+                "revoked api key {} labelled {}",
+                api_key_record.id, api_key_record.label
+            );
+        }
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "usage: shop-manager-api keys create <label> | keys list | keys revoke <id>",
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// How long the loaded api keys are trusted before the file's modification time
+/// is looked at again.
+pub const API_KEYS_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Active api keys by hash, the file is read again whenever it changes so keys
+/// created or revoked with the `keys` commands apply without a restart, within
+/// the reload interval.
+pub struct ApiKeyStore {
+    api_keys_file: String,
+    reload_interval: Duration,
+    loaded_api_keys: RwLock<LoadedApiKeys>,
+}
+
+#[derive(Default)]
+struct LoadedApiKeys {
+    modified: Option<SystemTime>,
+    checked_at: Option<Instant>,
+    labels: HashMap<String, String>,
+}
+
+impl ApiKeyStore {
+    pub fn new(api_keys_file: String) -> Self {
+        ApiKeyStore {
+            api_keys_file,
+            reload_interval: API_KEYS_RELOAD_INTERVAL,
+            loaded_api_keys: RwLock::new(LoadedApiKeys::default()),
+        }
+    }
+
+    pub fn with_reload_interval(mut self, reload_interval: Duration) -> Self {
+        self.reload_interval = reload_interval;
+        self
+    }
+
+    /// Label of the key when it is active.
+    pub fn authenticate(&self, api_key: &str) -> Option<String> {
+        let key_hash = hash_api_key(api_key);
+        {
+            let loaded_api_keys = self
+                .loaded_api_keys
+                .read()
+                .unwrap_or_else(|e| e.into_inner());
+            let is_fresh = loaded_api_keys.checked_at.map_or(false, |checked_at| {
+                checked_at.elapsed() < self.reload_interval
+            });
+            if is_fresh {
+                return loaded_api_keys.labels.get(&key_hash).cloned();
+            }
+        }
+
+        let modified = std::fs::metadata(&self.api_keys_file)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let mut loaded_api_keys = self
+            .loaded_api_keys
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        if loaded_api_keys.modified != modified {
+            loaded_api_keys.labels = match load_api_keys(&self.api_keys_file) {
+                Ok(api_keys) => api_keys
+                    .into_iter()
+                    .filter(|api_key| api_key.revoked_at.is_none())
+                    .map(|api_key| (api_key.key_hash, api_key.label))
+                    .collect(),
+                Err(e) => {
+                    error!("every api key is refused until the api keys file is fixed: {e}");
+                    HashMap::new()
+                }
+            };
+            loaded_api_keys.modified = modified;
+            info!("{} active api keys loaded", loaded_api_keys.labels.len());
+        }
+        loaded_api_keys.checked_at = Some(Instant::now());
+        loaded_api_keys.labels.get(&key_hash).cloned()
+    }
+}
+
+/// Requires a valid api key, in `X-Api-Key` or `Authorization: Bearer`, on
+/// every route but the health checks.
+pub struct ApiKeyAuth {
+    store: Option<Arc<ApiKeyStore>>,
+}
+
+impl ApiKeyAuth {
+    pub fn new(store: Arc<ApiKeyStore>) -> Self {
+        ApiKeyAuth { store: Some(store) }
+    }
+
+    /// Lets every request through, for servers only reachable by trusted clients.
+    pub fn disabled() -> Self {
+        ApiKeyAuth { store: None }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ApiKeyAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = ApiKeyAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiKeyAuthMiddleware {
+            service: Rc::new(service),
+            store: self.store.clone(),
+        }))
+    }
+}
+
+pub struct ApiKeyAuthMiddleware<S> {
+    service: Rc<S>,
+    store: Option<Arc<ApiKeyStore>>,
+}
+
+impl<S, B> Service<ServiceRequest> for ApiKeyAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let store = match &self.store {
+            Some(store) if !req.path().starts_with(PUBLIC_PATH_PREFIX) => store,
+            _ => {
+                return Box::pin(async move { Ok(service.call(req).await?.map_into_boxed_body()) })
+            }
+        };
+
+        let label = match get_request_api_key(&req) {
+            Some(api_key) => store
+                .authenticate(&api_key)
+                .ok_or("invalid or revoked api key"),
+            None => Err("an api key is required in X-Api-Key or Authorization: Bearer"),
+        };
+        match label {
+            Ok(label) => Box::pin(API_KEY_LABEL.scope(label, async move {
+                Ok(service.call(req).await?.map_into_boxed_body())
+            })),
+            Err(reason) => {
+                let response =
+                    req.error_response(errors::ShopApiError::Unauthorized(reason.to_string()));
+                Box::pin(async move { Ok(response) })
+            }
+        }
+    }
+}
+
+fn get_request_api_key(req: &ServiceRequest) -> Option<String> {
+    let headers = req.headers();
+    headers
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| {
+            headers
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+        })
+        .map(|api_key| api_key.trim().to_string())
+        .filter(|api_key| !api_key.is_empty())
+}
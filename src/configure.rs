@@ -1,57 +1,512 @@
-
 use super::*;
-pub fn get_environment_configurations() -> ShopResult<ShopConfigurations> {
-    dotenv().map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+use anchor_client::solana_sdk::native_token::sol_to_lamports;
+use log::warn;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+
+/// Path of the optional configuration file, read when `SHOP_CONFIG_FILE` is not set.
+const DEFAULT_CONFIG_FILE: &str = "shop.toml";
+/// Path of the file the initialized goods account is persisted to.
+const DEFAULT_STATE_FILE: &str = "shop_state.json";
+/// Path of the file the shops created through `POST /shops` are persisted to.
+const DEFAULT_SHOPS_FILE: &str = "shops.json";
+/// Path of the file the hashes of the api keys are kept in.
+const DEFAULT_API_KEYS_FILE: &str = "api_keys.json";
+
+/// Configuration keys, the file uses the same names in lower case.
+const CONFIGURATION_KEYS: &[&str] = &[
+    "HOST",
+    "PORT",
+    "UNIX_SOCKET",
+    "WORKERS",
+    "BACKLOG",
+    "KEEP_ALIVE_SECS",
+    "CLIENT_REQUEST_TIMEOUT_MS",
+    "CLIENT_DISCONNECT_TIMEOUT_MS",
+    "PROGRAM_ID",
+    "CLUSTER",
+    "CLUSTER_URL",
+    "CLUSTER_WS_URL",
+    "COMMITMENT",
+    "PAYER_KEY_PAIR",
+    "ACCOUNT_PUBKEY",
+    "ALLOW_EPHEMERAL_KEYS",
+    "STATE_FILE",
+    "SHOPS_FILE",
+    "API_KEYS_FILE",
+    "API_AUTH_DISABLED",
+    "AIRDROP_ENABLED",
+    "AIRDROP_MIN_BALANCE_SOL",
+    "AIRDROP_AMOUNT_SOL",
+    "AIRDROP_MAX_RETRIES",
+    "AIRDROP_BACKOFF_MS",
+    "HEALTH_MIN_BALANCE_SOL",
+    "IDEMPOTENCY_TTL_SECS",
+];
+const SECRET_CONFIGURATION_KEYS: &[&str] = &["PAYER_KEY_PAIR", "ACCOUNT_PUBKEY"];
+
+/// Loads the `.env` file into the process environment, variables that are
+/// already set in the process take precedence over the ones in the file.
+pub fn load_dotenv_file() -> ShopResult<Option<PathBuf>> {
+    match dotenv() {
+        Ok(path) => Ok(Some(path)),
+        Err(dotenv::Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Box::new(errors::ShopCustomError::getCustomError(e))),
+    }
+}
+
+/// Reads the optional TOML/YAML configuration file, the format is picked from
+/// the file extension.
+pub fn load_configuration_file() -> ShopResult<ShopConfigurationsFile> {
+    let (path, explicitly_set) = match env::var("SHOP_CONFIG_FILE") {
+        Ok(path) => (path, true),
+        Err(_) => (DEFAULT_CONFIG_FILE.to_string(), false),
+    };
+    if !Path::new(&path).exists() {
+        if explicitly_set {
+            return Err(Box::new(errors::ShopCustomError(format!(
+                "configuration file {path} does not exist"
+            ))));
+        }
+        return Ok(ShopConfigurationsFile::default());
+    }
+
+    let contents = std::fs::read_to_string(&path)?;
+    info!("loaded configuration file {path}");
+    parse_configuration_file(&path, &contents)
+}
+
+pub fn parse_configuration_file(path: &str, contents: &str) -> ShopResult<ShopConfigurationsFile> {
+    let configurations_file = if path.ends_with(".yaml") || path.ends_with(".yml") {
+        serde_yaml::from_str(contents)?
+    } else if path.ends_with(".toml") {
+        toml::from_str(contents)?
+    } else {
+        return Err(Box::new(errors::ShopCustomError(format!(
+            "unsupported configuration file {path}, expected .toml, .yaml or .yml"
+        ))));
+    };
+    Ok(configurations_file)
+}
+
+/// Resolves a configuration value, the process environment (including the
+/// `.env` file) wins over the configuration file.
+fn get_configuration_value(
+    key: &str,
+    configurations_file: &ShopConfigurationsFile,
+) -> Option<String> {
+    env::var(key)
+        .ok()
+        .filter(|value| !value.is_empty())
+        .or_else(|| configurations_file.get(key))
+}
+
+fn get_required_configuration_value(
+    key: &str,
+    configurations_file: &ShopConfigurationsFile,
+) -> ShopResult<String> {
+    get_configuration_value(key, configurations_file).ok_or_else(|| {
+        errors::ShopCustomError(format!("missing required configuration value {key}")).into()
+    })
+}
 
-    for (key, value) in env::vars() {
+pub fn get_environment_configurations(
+    configurations_file: &ShopConfigurationsFile,
+) -> ShopResult<ShopConfigurations> {
+    for key in CONFIGURATION_KEYS.iter().copied() {
+        let value = match get_configuration_value(key, configurations_file) {
+            Some(_) if SECRET_CONFIGURATION_KEYS.contains(&key) => "<redacted>".to_string(),
+            Some(value) => value,
+            None => "<not set>".to_string(),
+        };
         info!("config:  Key:{key} Value:{value}");
     }
 
-    let host = dotenv!("HOST");
-    let port = dotenv!("PORT");
-    let program_id = dotenv!("PROGRAM_ID");
-    let cluster = dotenv!("CLUSTER");
-    let cluster_url = dotenv!("CLUSTER_URL");
-    let cluster_ws_url = dotenv!("CLUSTER_WS_URL");
+    let host = get_configuration_value("HOST", configurations_file)
+        .unwrap_or_else(|| "127.0.0.1".to_string());
+    let port =
+        get_configuration_value("PORT", configurations_file).unwrap_or_else(|| "8080".to_string());
+    let server = get_server_configurations(&host, &port, configurations_file)?;
+    let funding_policy = get_funding_policy(configurations_file)?;
+    let program_id = get_required_configuration_value("PROGRAM_ID", configurations_file)?;
+    shop_solana_utils::try_get_program_id(&program_id)?;
+    let cluster = get_configuration_value("CLUSTER", configurations_file)
+        .unwrap_or_else(|| "localnet".to_string());
+    let cluster_url =
+        get_configuration_value("CLUSTER_URL", configurations_file).unwrap_or_default();
+    let cluster_ws_url =
+        get_configuration_value("CLUSTER_WS_URL", configurations_file).unwrap_or_default();
+    // reject typos up front rather than silently targeting another network
+    let resolved_cluster =
+        shop_anchor_utils::try_parse_cluster(&cluster, &cluster_url, &cluster_ws_url)
+            .map_err(errors::ShopCustomError)?;
+    info!(
+        "cluster:{resolved_cluster} rpc:{} ws:{}",
+        resolved_cluster.url(),
+        resolved_cluster.ws_url()
+    );
 
-    let optional_payer_key_pair = option_env!("PAYER_KEY_PAIR");
-    let optional_account_key_pair = option_env!("ACCOUNT_PUBKEY");
+    let commitment = match get_configuration_value("COMMITMENT", configurations_file) {
+        Some(value) => shop_solana_utils::parse_commitment(&value).ok_or_else(|| {
+            errors::ShopCustomError(format!(
+                "COMMITMENT `{value}` must be one of processed, confirmed or finalized"
+            ))
+        })?,
+        None => CommitmentConfig::confirmed(),
+    };
 
-    let payer_key_pair_bytes =
-        shop_solana_utils::get_key_pair_bytes_from_env_string_return_random_if_no_key_string_found(
-            optional_payer_key_pair,
-        );
-    let account_key_pair_bytes =
-        shop_solana_utils::get_key_pair_bytes_from_env_string_return_random_if_no_key_string_found(
-            optional_account_key_pair,
-        );
+    let mut problems = Vec::new();
+    let health_min_balance_sol =
+        parse_optional_number::<f64>("HEALTH_MIN_BALANCE_SOL", configurations_file, &mut problems)
+            .unwrap_or(0.01);
+    if health_min_balance_sol < 0.0 {
+        problems.push("HEALTH_MIN_BALANCE_SOL can not be negative".to_string());
+    }
+    let idempotency_ttl_secs =
+        parse_optional_number::<u64>("IDEMPOTENCY_TTL_SECS", configurations_file, &mut problems)
+            .unwrap_or(24 * 60 * 60);
+    if !problems.is_empty() {
+        return Err(Box::new(errors::ShopCustomError(format!(
+            "invalid configuration: {}",
+            problems.join("; ")
+        ))));
+    }
+
+    let optional_payer_key_pair = get_configuration_value("PAYER_KEY_PAIR", configurations_file);
+    let optional_account_key_pair = get_configuration_value("ACCOUNT_PUBKEY", configurations_file);
+
+    let allow_ephemeral_keys = get_flag("ALLOW_EPHEMERAL_KEYS", configurations_file);
+    let payer_key_pair_bytes = shop_solana_utils::get_key_pair_bytes(
+        "PAYER_KEY_PAIR",
+        optional_payer_key_pair.as_deref(),
+        allow_ephemeral_keys,
+    )?;
+
+    // a configured ACCOUNT_PUBKEY always wins, otherwise reuse the goods account
+    // initialized by a previous run before falling back to an ephemeral one
+    let state_file = get_configuration_value("STATE_FILE", configurations_file)
+        .unwrap_or_else(|| DEFAULT_STATE_FILE.to_string());
+    let persisted_shop_state = load_persisted_shop_state(&state_file)?;
+    let account_key_pair_bytes = match (&optional_account_key_pair, persisted_shop_state) {
+        (None, Some(persisted_shop_state)) => {
+            info!(
+                "using goods account {} from {state_file}",
+                persisted_shop_state.goods_account
+            );
+            persisted_shop_state.key_pair_bytes()?
+        }
+        _ => shop_solana_utils::get_key_pair_bytes(
+            "ACCOUNT_PUBKEY",
+            optional_account_key_pair.as_deref(),
+            allow_ephemeral_keys,
+        )?,
+    };
+
+    let shops_file = get_configuration_value("SHOPS_FILE", configurations_file)
+        .unwrap_or_else(|| DEFAULT_SHOPS_FILE.to_string());
+
+    let api_keys_file = get_configuration_value("API_KEYS_FILE", configurations_file)
+        .unwrap_or_else(|| DEFAULT_API_KEYS_FILE.to_string());
+    let api_auth_enabled = !get_flag("API_AUTH_DISABLED", configurations_file);
+    if !api_auth_enabled {
+        warn!("API_AUTH_DISABLED is set, every client that reaches the server can use it");
+    }
 
     let configurations = ShopConfigurations {
-        host: host.to_string(),
-        port: port.to_string(),
-        program_id: program_id.to_string(),
-        cluster: cluster.to_string(),
-        cluster_url: cluster_url.to_string(),
-        cluster_ws_url: cluster_ws_url.to_string(),
+        host,
+        port,
+        server,
+        funding_policy,
+        program_id,
+        cluster,
+        cluster_url,
+        cluster_ws_url,
+        commitment,
         payer_key_pair_bytes,
         account_key_pair_bytes,
+        state_file: match optional_account_key_pair {
+            Some(_) => None,
+            None => Some(state_file),
+        },
+        shops_file,
+        api_keys_file,
+        api_auth_enabled,
+        health_min_balance_lamports: sol_to_lamports(health_min_balance_sol),
+        idempotency_ttl_secs,
     };
 
     Ok(configurations)
 }
+
+/// Reads the goods account persisted by `save_persisted_shop_state`, if any.
+pub fn load_persisted_shop_state(state_file: &str) -> ShopResult<Option<PersistedShopState>> {
+    if !Path::new(state_file).exists() {
+        return Ok(None);
+    }
+    let contents = std::fs::read_to_string(state_file)?;
+    let persisted_shop_state = serde_json::from_str(&contents).map_err(|e| {
+        errors::ShopCustomError(format!("state file {state_file} is corrupted: {e}"))
+    })?;
+    Ok(Some(persisted_shop_state))
+}
+
+/// Stores the goods account keypair so the server keeps using the same
+/// account across restarts, the file is only readable by its owner.
+pub fn save_persisted_shop_state(
+    state_file: &str,
+    persisted_shop_state: &PersistedShopState,
+) -> ShopResult<()> {
+    let contents = serde_json::to_string_pretty(persisted_shop_state)?;
+    write_private_file(state_file, &contents)?;
+    info!(
+        "goods account {} saved to {state_file}",
+        persisted_shop_state.goods_account
+    );
+    Ok(())
+}
+
+/// Reads the shops persisted by `save_persisted_shops`, none when the file
+/// does not exist yet.
+pub fn load_shop_registry(shops_file: &str) -> ShopResult<ShopRegistry> {
+    let shops = if Path::new(shops_file).exists() {
+        let contents = std::fs::read_to_string(shops_file)?;
+        serde_json::from_str(&contents).map_err(|e| {
+            errors::ShopCustomError(format!("shops file {shops_file} is corrupted: {e}"))
+        })?
+    } else {
+        Vec::new()
+    };
+    Ok(ShopRegistry::new(shops_file.to_string(), shops))
+}
+
+/// Stores the goods account keypairs of every shop, the file is only readable
+/// by its owner.
+pub fn save_persisted_shops(shops_file: &str, shops: &[PersistedShopState]) -> ShopResult<()> {
+    let contents = serde_json::to_string_pretty(shops)?;
+    write_private_file(shops_file, &contents)?;
+    info!("{} shops saved to {shops_file}", shops.len());
+    Ok(())
+}
+
+pub fn write_private_file(path: &str, contents: &str) -> ShopResult<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    std::io::Write::write_all(&mut file, contents.as_bytes())?;
+    Ok(())
+}
+
+/// Validates the http server settings, every problem found is reported in a
+/// single error so they can all be fixed at once.
+pub fn get_server_configurations(
+    host: &str,
+    port: &str,
+    configurations_file: &ShopConfigurationsFile,
+) -> ShopResult<ShopServerConfigurations> {
+    let mut problems = Vec::new();
+
+    let port = match port.parse::<u16>() {
+        Ok(port) => Some(port),
+        Err(_) => {
+            problems.push(format!("PORT `{port}` is not a valid port number"));
+            None
+        }
+    };
+
+    let mut bind_addresses = Vec::new();
+    for host in host
+        .split(',')
+        .map(str::trim)
+        .filter(|host| !host.is_empty())
+    {
+        // ipv6 addresses may be written in their bracketed form, e.g. [::1]
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = match port {
+            Some(port) => port,
+            None => continue,
+        };
+        match (host, port).to_socket_addrs() {
+            Ok(addresses) => bind_addresses.extend(addresses),
+            Err(e) => problems.push(format!("HOST `{host}` can not be resolved: {e}")),
+        }
+    }
+
+    let unix_sockets: Vec<String> = get_configuration_value("UNIX_SOCKET", configurations_file)
+        .map(|sockets| {
+            sockets
+                .split(',')
+                .map(str::trim)
+                .filter(|socket| !socket.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    if !unix_sockets.is_empty() && !cfg!(unix) {
+        problems.push("UNIX_SOCKET is only supported on unix platforms".to_string());
+    }
+
+    if bind_addresses.is_empty() && unix_sockets.is_empty() && problems.is_empty() {
+        problems.push("no HOST or UNIX_SOCKET to listen on was configured".to_string());
+    }
+
+    let workers = parse_optional_number::<usize>("WORKERS", configurations_file, &mut problems);
+    if workers == Some(0) {
+        problems.push("WORKERS must be greater than 0".to_string());
+    }
+    let backlog = parse_optional_number::<u32>("BACKLOG", configurations_file, &mut problems);
+    let keep_alive_secs =
+        parse_optional_number::<u64>("KEEP_ALIVE_SECS", configurations_file, &mut problems);
+    let client_request_timeout_ms = parse_optional_number::<u64>(
+        "CLIENT_REQUEST_TIMEOUT_MS",
+        configurations_file,
+        &mut problems,
+    );
+    let client_disconnect_timeout_ms = parse_optional_number::<u64>(
+        "CLIENT_DISCONNECT_TIMEOUT_MS",
+        configurations_file,
+        &mut problems,
+    );
+
+    if !problems.is_empty() {
+        return Err(Box::new(errors::ShopCustomError(format!(
+            "invalid server configuration: {}",
+            problems.join("; ")
+        ))));
+    }
+
+    Ok(ShopServerConfigurations {
+        bind_addresses,
+        unix_sockets,
+        workers,
+        backlog,
+        keep_alive_secs,
+        client_request_timeout_ms,
+        client_disconnect_timeout_ms,
+    })
+}
+
+/// Reads when and how much the payer gets airdropped, by default airdrops are off.
+pub fn get_funding_policy(
+    configurations_file: &ShopConfigurationsFile,
+) -> ShopResult<FundingPolicy> {
+    let mut problems = Vec::new();
+
+    let airdrop_enabled = get_flag("AIRDROP_ENABLED", configurations_file);
+    let min_balance_sol = parse_optional_number::<f64>(
+        "AIRDROP_MIN_BALANCE_SOL",
+        configurations_file,
+        &mut problems,
+    )
+    .unwrap_or(1.0);
+    let airdrop_amount_sol =
+        parse_optional_number::<f64>("AIRDROP_AMOUNT_SOL", configurations_file, &mut problems)
+            .unwrap_or(1.0);
+    let airdrop_max_retries =
+        parse_optional_number::<u32>("AIRDROP_MAX_RETRIES", configurations_file, &mut problems)
+            .unwrap_or(3);
+    let airdrop_backoff_ms =
+        parse_optional_number::<u64>("AIRDROP_BACKOFF_MS", configurations_file, &mut problems)
+            .unwrap_or(1000);
+
+    if min_balance_sol < 0.0 {
+        problems.push("AIRDROP_MIN_BALANCE_SOL can not be negative".to_string());
+    }
+    if airdrop_amount_sol <= 0.0 || airdrop_amount_sol > shop_solana_utils::MAX_AIRDROP_SOL {
+        problems.push(format!(
+            "AIRDROP_AMOUNT_SOL must be greater than 0 and at most {}",
+            shop_solana_utils::MAX_AIRDROP_SOL
+        ));
+    }
+    if airdrop_max_retries > shop_solana_utils::MAX_AIRDROP_RETRIES {
+        problems.push(format!(
+            "AIRDROP_MAX_RETRIES can not be more than {}",
+            shop_solana_utils::MAX_AIRDROP_RETRIES
+        ));
+    }
+    if airdrop_backoff_ms > shop_solana_utils::MAX_AIRDROP_BACKOFF_MS {
+        problems.push(format!(
+            "AIRDROP_BACKOFF_MS can not be more than {}",
+            shop_solana_utils::MAX_AIRDROP_BACKOFF_MS
+        ));
+    }
+    if !problems.is_empty() {
+        return Err(Box::new(errors::ShopCustomError(format!(
+            "invalid airdrop configuration: {}",
+            problems.join("; ")
+        ))));
+    }
+
+    Ok(FundingPolicy {
+        airdrop_enabled,
+        min_balance_lamports: sol_to_lamports(min_balance_sol),
+        airdrop_lamports: sol_to_lamports(airdrop_amount_sol),
+        airdrop_max_retries,
+        airdrop_backoff_ms,
+    })
+}
+
+fn get_flag(key: &str, configurations_file: &ShopConfigurationsFile) -> bool {
+    get_configuration_value(key, configurations_file)
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false)
+}
+
+fn parse_optional_number<T: std::str::FromStr>(
+    key: &str,
+    configurations_file: &ShopConfigurationsFile,
+    problems: &mut Vec<String>,
+) -> Option<T> {
+    let value = get_configuration_value(key, configurations_file)?;
+    match value.parse::<T>() {
+        Ok(number) => Some(number),
+        Err(_) => {
+            problems.push(format!("{key} `{value}` is not a valid number"));
+            None
+        }
+    }
+}
+
 pub fn setup_environment_and_get_configurations() -> ShopResult<ShopConfigurations> {
-    let shop_configurations = get_environment_configurations()?;
+    // the .env file has to be loaded before the logger so RUST_LOG in it is honoured
+    let dotenv_path = load_dotenv_file()?;
 
     // setup our default logging format with levels according to .env
-    env_logger::init();
+    let _ = env_logger::try_init();
+    match dotenv_path {
+        Some(path) => info!("loaded environment from {}", path.display()),
+        None => info!("no .env file found, using process environment only"),
+    }
+
+    let configurations_file = load_configuration_file()?;
+    let shop_configurations = get_environment_configurations(&configurations_file)?;
     info!("environment setup complete");
     Ok(shop_configurations)
 }
 
+/// Only reads what the `keys` commands need, so api keys can be managed
+/// without a complete configuration.
+pub fn setup_environment_and_get_api_keys_file() -> ShopResult<String> {
+    load_dotenv_file()?;
+    let configurations_file = load_configuration_file()?;
+    let api_keys_file = get_configuration_value("API_KEYS_FILE", &configurations_file)
+        .unwrap_or_else(|| DEFAULT_API_KEYS_FILE.to_string());
+    Ok(api_keys_file)
+}
+
 pub fn get_shop_state(shop_configurations: &ShopConfigurations) -> ShopResult<ShopState> {
-    let program = shop_anchor_utils::try_get_program(shop_configurations)?;
+    let program_client = shop_anchor_utils::ShopProgramClient::new(shop_configurations)?;
+    let goods_account_key_pair =
+        shop_solana_utils::keypair_from_bytes(&shop_configurations.account_key_pair_bytes)?;
     let shop_state = ShopState {
         shop_configurations: shop_configurations,
+        program_client,
+        goods_account_key_pair,
+        goods_account_locks: Default::default(),
     };
     Ok(shop_state)
-}
\ No newline at end of file
+}
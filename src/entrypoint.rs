@@ -1,28 +1,190 @@
-
 use super::*;
+use actix_web::http::KeepAlive;
+use actix_web::middleware::NormalizePath;
+use log::warn;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 pub async fn start_server(shop_configurations:&'static ShopConfigurations)->std::io::Result<()>{
-    shop_solana_utils::request_airdrop_for_current_wallet(&shop_configurations);
-    HttpServer::new( move || {
-        let shop_state = match configure::get_shop_state(&shop_configurations) {
-            Ok(shop_state) => shop_state,
-            Err(e) => {
-                error!("{e:#}");
-                panic!("{e:#}");
-            }
+    // the workers build their own state, this one only verifies it can be built
+    // and that the cluster matches the configuration before listening
+    let shop_state = configure::get_shop_state(shop_configurations)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+    verify_deployment(&shop_state).await?;
+    report_and_fund_payer(&shop_state).await;
+
+    let shop_registry = configure::load_shop_registry(&shop_configurations.shops_file)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+    reconcile_pending_shops(&shop_state, &shop_registry).await;
+    info!("serving {} shops besides the configured one", shop_registry.summaries().len());
+    // shared by every worker so a shop created on one is served by all of them
+    let shop_registry = Data::new(shop_registry);
+
+    let api_key_store = if shop_configurations.api_auth_enabled {
+        Some(Arc::new(load_api_key_store(shop_configurations)?))
+    } else {
+        None
+    };
+
+    let server_configurations = &shop_configurations.server;
+    // shared by every worker so retries are recognised whichever worker serves them
+    let idempotency_store = Arc::new(IdempotencyStore::new(Duration::from_secs(
+        shop_configurations.idempotency_ttl_secs,
+    )));
+    // shared by every worker so the writes to a goods account wait for each other
+    let goods_account_locks = Arc::new(GoodsAccountLocks::default());
+    let mut server = HttpServer::new( move || {
+        let mut shop_state = configure::get_shop_state(&shop_configurations)
+            .expect("the shop state is verified before the server starts");
+        shop_state.goods_account_locks = goods_account_locks.clone();
+
+        let api_key_auth = match &api_key_store {
+            Some(api_key_store) => ApiKeyAuth::new(api_key_store.clone()),
+            None => ApiKeyAuth::disabled(),
         };
 
         App::new()
+            .wrap(Idempotency::new(idempotency_store.clone()))
+            .wrap(api_key_auth)
             .wrap(Logger::default())
+            .wrap(NormalizePath::trim())
             .app_data(Data::new(shop_state))
-            .service(routes::initialize)
-            .service(routes::insert_goods)
-    })
-    .bind(("127.0.0.1", 8080))?
-    .run()
-    .await
+            .app_data(shop_registry.clone())
+            .configure(routes::configure_routes)
+    });
+
+    if let Some(workers) = server_configurations.workers {
+        server = server.workers(workers);
+    }
+    if let Some(backlog) = server_configurations.backlog {
+        server = server.backlog(backlog);
+    }
+    if let Some(keep_alive_secs) = server_configurations.keep_alive_secs {
+        server = match keep_alive_secs {
+            0 => server.keep_alive(KeepAlive::Disabled),
+            secs => server.keep_alive(Duration::from_secs(secs)),
+        };
+    }
+    if let Some(timeout_ms) = server_configurations.client_request_timeout_ms {
+        server = server.client_request_timeout(Duration::from_millis(timeout_ms));
+    }
+    if let Some(timeout_ms) = server_configurations.client_disconnect_timeout_ms {
+        server = server.client_disconnect_timeout(Duration::from_millis(timeout_ms));
+    }
+
+    for address in &server_configurations.bind_addresses {
+        server = server
+            .bind(address)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("failed to bind {address}: {e}")))?;
+        info!("listening on http://{address}");
+    }
+    #[cfg(unix)]
+    for socket in &server_configurations.unix_sockets {
+        server = server
+            .bind_uds(socket)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("failed to bind {socket}: {e}")))?;
+        info!("listening on unix:{socket}");
+    }
+
+    server.run().await
 }
 
+/// Refuses to start when the program or the goods account do not match the
+/// configuration, every problem is logged so they can all be fixed at once.
+async fn verify_deployment(shop_state: &ShopState<'_>) -> std::io::Result<()> {
+    let problems = shop_state
+        .program_client
+        .verify_deployment(shop_state.goods_account_key_pair.pubkey())
+        .await;
+    if problems.is_empty() {
+        info!("program {} verified", shop_state.program_client.program_id);
+        return Ok(());
+    }
+
+    for problem in &problems {
+        error!("startup verification: {problem}");
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("startup verification failed: {}", problems.join("; ")),
+    ))
+}
+
+/// Serves the pending shops whose account was initialized after all, e.g. when
+/// the server stopped before the Initialize transaction was confirmed. The
+/// others stay pending, their keypair is kept in the shops file.
+pub async fn reconcile_pending_shops(shop_state: &ShopState<'_>, shop_registry: &ShopRegistry) {
+    for goods_account in shop_registry.pending_goods_accounts() {
+        match reconcile_pending_shop(shop_state, shop_registry, &goods_account).await {
+            Ok(true) => info!("pending shop {goods_account} is initialized, it is now served"),
+            Ok(false) => {
+                warn!("shop {goods_account} is still pending, its account is not initialized")
+            }
+            Err(e) => error!("failed to check the pending shop {goods_account}: {e}"),
+        }
+    }
+}
 
+async fn reconcile_pending_shop(
+    shop_state: &ShopState<'_>,
+    shop_registry: &ShopRegistry,
+    goods_account: &str,
+) -> ShopResult<bool> {
+    let goods_account_pubkey = Pubkey::from_str(goods_account)?;
+    let program_client = &shop_state.program_client;
+    let (_, initialized) = program_client
+        .fetch_goods_account_status(
+            goods_account_pubkey,
+            shop_state.shop_configurations.commitment,
+        )
+        .await?;
+    if !initialized {
+        return Ok(false);
+    }
+    let signature = program_client
+        .fetch_initialize_signature(goods_account_pubkey)
+        .await?
+        .ok_or_else(|| {
+            errors::ShopCustomError("the rpc node has no transaction of the account".to_string())
+        })?;
+    shop_registry.mark_initialized(goods_account, &signature)?;
+    Ok(true)
+}
 
+/// A corrupted api keys file stops the server, a missing one only leaves it
+/// refusing every request until a key is created.
+fn load_api_key_store(shop_configurations: &ShopConfigurations) -> std::io::Result<ApiKeyStore> {
+    let api_keys_file = &shop_configurations.api_keys_file;
+    let api_keys = api_keys::load_api_keys(api_keys_file)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+    let active_api_keys = api_keys
+        .iter()
+        .filter(|api_key| api_key.revoked_at.is_none())
+        .count();
+    if active_api_keys == 0 {
+        warn!(
+            "no active api key in {api_keys_file}, every request is refused until one is created \
+             with `shop-manager-api keys create <label>`"
+        );
+    } else {
+        info!("{active_api_keys} active api keys in {api_keys_file}");
+    }
+    Ok(ApiKeyStore::new(api_keys_file.clone()))
+}
 
+/// Funding problems are only reported, the server still starts so operators
+/// can inspect and fund the payer through the api.
+async fn report_and_fund_payer(shop_state: &ShopState<'_>) {
+    let shop_configurations = shop_state.shop_configurations;
+    let cluster = shop_anchor_utils::get_cluster(shop_configurations);
+    if let Err(e) = shop_solana_utils::fund_payer_if_needed(
+        &shop_state.program_client,
+        &cluster,
+        &shop_configurations.funding_policy,
+    )
+    .await
+    {
+        error!("failed to check or fund the payer: {e}");
+    }
+}
@@ -1,4 +1,16 @@
 use super::*;
+use actix_web::http::StatusCode;
+use anchor_client::anchor_lang::error::{ErrorCode as AnchorErrorCode, ERROR_CODE_OFFSET};
+use anchor_client::solana_client::client_error::{
+    ClientError as SolanaClientError, ClientErrorKind,
+};
+use anchor_client::solana_client::rpc_request::{RpcError, RpcResponseErrorData};
+use anchor_client::solana_sdk::instruction::InstructionError;
+use anchor_client::solana_sdk::transaction::TransactionError;
+use anchor_client::ClientError;
+use serde::Serialize;
+use serde_json::{json, Value};
+use shop_manager::ErrorCode as ShopErrorCode;
 use std::fmt::Debug;
 #[derive(Debug)]
 pub struct ShopCustomError(pub String);
@@ -17,5 +29,351 @@ impl Error for ShopCustomError {}
 #[derive(Error, Display, Debug)]
 struct ShopResponseError(ShopCustomError);
 
-impl ResponseError for ShopCustomError {}
-impl ResponseError for ShopResponseError {}
\ No newline at end of file
+impl ResponseError for ShopCustomError {
+    fn error_response(&self) -> HttpResponse {
+        ShopApiError::Internal(self.0.clone()).error_response()
+    }
+}
+impl ResponseError for ShopResponseError {}
+
+/// Errors returned to API clients, each variant maps to its own status code
+/// and is rendered as a `{"status","code","reason","details"}` JSON body.
+#[derive(Debug)]
+pub enum ShopApiError {
+    Validation { reason: String, details: Value },
+    GoodNotFound(u64),
+    GoodAlreadyExists(u64),
+    ShopNotFound(String),
+    InvalidGood(Vec<FieldError>),
+    AccountNotFound(String),
+    TransactionNotFound(String),
+    IdempotencyKeyReused(String),
+    PreconditionFailed(String),
+    Unauthorized(String),
+    Rpc(String),
+    Signing(String),
+    InsufficientFunds(String),
+    Program(ShopProgramError),
+    Internal(String),
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ErrorResponseBody {
+    pub status: u16,
+    pub code: &'static str,
+    pub reason: String,
+    pub details: Value,
+}
+
+impl ShopApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ShopApiError::Validation { .. } => "VALIDATION_ERROR",
+            ShopApiError::GoodNotFound(_) => "GOOD_NOT_FOUND",
+            ShopApiError::GoodAlreadyExists(_) => "GOOD_ALREADY_EXISTS",
+            ShopApiError::ShopNotFound(_) => "SHOP_NOT_FOUND",
+            ShopApiError::InvalidGood(_) => "INVALID_GOOD",
+            ShopApiError::AccountNotFound(_) => "ACCOUNT_NOT_FOUND",
+            ShopApiError::TransactionNotFound(_) => "TRANSACTION_NOT_FOUND",
+            ShopApiError::IdempotencyKeyReused(_) => "IDEMPOTENCY_KEY_REUSED",
+            ShopApiError::PreconditionFailed(_) => "PRECONDITION_FAILED",
+            ShopApiError::Unauthorized(_) => "UNAUTHORIZED",
+            ShopApiError::Rpc(_) => "RPC_ERROR",
+            ShopApiError::Signing(_) => "SIGNING_ERROR",
+            ShopApiError::InsufficientFunds(_) => "INSUFFICIENT_FUNDS",
+            ShopApiError::Program(program_error) => program_error.mapping().1,
+            ShopApiError::Internal(_) => "INTERNAL_ERROR",
+        }
+    }
+
+    pub fn details(&self) -> Value {
+        match self {
+            ShopApiError::Validation { details, .. } => details.clone(),
+            ShopApiError::GoodNotFound(id) | ShopApiError::GoodAlreadyExists(id) => {
+                json!({ "id": id })
+            }
+            ShopApiError::ShopNotFound(shop_id) => json!({ "shop_id": shop_id }),
+            ShopApiError::InvalidGood(field_errors) => json!({ "fields": field_errors }),
+            ShopApiError::AccountNotFound(address) => json!({ "address": address }),
+            ShopApiError::TransactionNotFound(signature) => json!({ "signature": signature }),
+            ShopApiError::IdempotencyKeyReused(key) => json!({ "idempotency_key": key }),
+            ShopApiError::PreconditionFailed(etag) => json!({ "etag": etag }),
+            ShopApiError::Program(program_error) => json!({
+                "program_error_code": program_error.code,
+                "program_error_name": program_error.name,
+                "logs": program_error.logs,
+            }),
+            _ => Value::Null,
+        }
+    }
+
+    pub fn to_response_body(&self) -> ErrorResponseBody {
+        ErrorResponseBody {
+            status: self.status_code().as_u16(),
+            code: self.code(),
+            reason: self.to_string(),
+            details: self.details(),
+        }
+    }
+}
+
+impl fmt::Display for ShopApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShopApiError::Validation { reason, .. } => write!(f, "{reason}"),
+            ShopApiError::GoodNotFound(_) => write!(f, "Not found"),
+            ShopApiError::GoodAlreadyExists(_) => write!(f, "A good with this id already exists"),
+            ShopApiError::ShopNotFound(_) => write!(f, "shop not found"),
+            ShopApiError::InvalidGood(field_errors) => write!(
+                f,
+                "invalid good: {}",
+                field_errors
+                    .iter()
+                    .map(|field_error| format!("{} {}", field_error.field, field_error.message))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ShopApiError::AccountNotFound(_) => write!(f, "account not found"),
+            ShopApiError::TransactionNotFound(_) => write!(f, "transaction not found"),
+            ShopApiError::IdempotencyKeyReused(_) => {
+                write!(
+                    f,
+                    "Idempotency-Key was already used for a different request"
+                )
+            }
+            ShopApiError::PreconditionFailed(_) => {
+                write!(f, "the goods changed since they were read")
+            }
+            ShopApiError::Unauthorized(reason) => write!(f, "{reason}"),
+            ShopApiError::Rpc(reason) => write!(f, "cluster request failed: {reason}"),
+            ShopApiError::Signing(reason) => write!(f, "signing failed: {reason}"),
+            ShopApiError::InsufficientFunds(reason) => write!(f, "insufficient funds: {reason}"),
+            ShopApiError::Program(program_error) => write!(f, "{}", program_error.message),
+            ShopApiError::Internal(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+impl Error for ShopApiError {}
+
+impl ResponseError for ShopApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ShopApiError::Validation { .. } => StatusCode::BAD_REQUEST,
+            ShopApiError::GoodNotFound(_)
+            | ShopApiError::ShopNotFound(_)
+            | ShopApiError::AccountNotFound(_)
+            | ShopApiError::TransactionNotFound(_) => StatusCode::NOT_FOUND,
+            ShopApiError::GoodAlreadyExists(_) => StatusCode::CONFLICT,
+            ShopApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ShopApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ShopApiError::InvalidGood(_) | ShopApiError::IdempotencyKeyReused(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ShopApiError::Rpc(_) => StatusCode::BAD_GATEWAY,
+            ShopApiError::InsufficientFunds(_) => StatusCode::PAYMENT_REQUIRED,
+            ShopApiError::Program(program_error) => program_error.mapping().0,
+            ShopApiError::Signing(_) | ShopApiError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        error!("{self}");
+        HttpResponse::build(self.status_code()).json(self.to_response_body())
+    }
+}
+
+impl From<ShopCustomError> for ShopApiError {
+    fn from(e: ShopCustomError) -> Self {
+        ShopApiError::Internal(e.0)
+    }
+}
+
+impl From<ClientError> for ShopApiError {
+    fn from(e: ClientError) -> Self {
+        match e {
+            ClientError::AccountNotFound => ShopApiError::AccountNotFound(String::new()),
+            ClientError::SolanaClientError(e) => ShopApiError::from(e),
+            e => ShopApiError::Program(ShopProgramError {
+                code: None,
+                name: None,
+                message: e.to_string(),
+                logs: Vec::new(),
+            }),
+        }
+    }
+}
+
+impl From<SolanaClientError> for ShopApiError {
+    fn from(e: SolanaClientError) -> Self {
+        match e.get_transaction_error() {
+            Some(transaction_error) => {
+                decode_transaction_error(&transaction_error, get_transaction_logs(&e))
+            }
+            None => ShopApiError::Rpc(e.to_string()),
+        }
+    }
+}
+
+/// Maps a failed transaction to the api error clients would have received had
+/// they sent it, decoding shop_manager errors from the program `logs`.
+pub fn decode_transaction_error(
+    transaction_error: &TransactionError,
+    logs: Vec<String>,
+) -> ShopApiError {
+    match transaction_error {
+        TransactionError::InsufficientFundsForFee
+        | TransactionError::InsufficientFundsForRent { .. }
+        | TransactionError::InstructionError(_, InstructionError::InsufficientFunds) => {
+            ShopApiError::InsufficientFunds(transaction_error.to_string())
+        }
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+            ShopApiError::Program(ShopProgramError::from_logs(
+                Some(*code),
+                transaction_error.to_string(),
+                logs,
+            ))
+        }
+        _ => ShopApiError::Program(ShopProgramError::from_logs(
+            None,
+            transaction_error.to_string(),
+            logs,
+        )),
+    }
+}
+
+/// Name and number of an error of the shop_manager program, or of anchor itself,
+/// taken from the error enums so the table below fails to compile when one of
+/// them renames or drops a variant.
+macro_rules! anchor_error {
+    (shop_manager::$variant:ident) => {
+        (
+            stringify!($variant),
+            ShopErrorCode::$variant as u32 + ERROR_CODE_OFFSET,
+        )
+    };
+    (anchor::$variant:ident) => {
+        (stringify!($variant), AnchorErrorCode::$variant as u32)
+    };
+}
+
+/// Anchor error codes raised by the shop_manager program (or by Anchor itself
+/// on its accounts) that clients should be able to tell apart, by name when the
+/// logs carry it and by number when they are not available.
+const PROGRAM_ERROR_MAPPINGS: &[((&str, u32), StatusCode, &str)] = &[
+    (
+        anchor_error!(shop_manager::GoodAlreadyExists),
+        StatusCode::CONFLICT,
+        "GOOD_ALREADY_EXISTS",
+    ),
+    (
+        anchor_error!(shop_manager::GoodNotFound),
+        StatusCode::NOT_FOUND,
+        "GOOD_NOT_FOUND",
+    ),
+    (
+        anchor_error!(shop_manager::GoodsAccountFull),
+        StatusCode::INSUFFICIENT_STORAGE,
+        "GOODS_ACCOUNT_FULL",
+    ),
+    // anchor fails to write the account back once the goods outgrow its space
+    (
+        anchor_error!(anchor::AccountDidNotSerialize),
+        StatusCode::INSUFFICIENT_STORAGE,
+        "GOODS_ACCOUNT_FULL",
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShopProgramError {
+    pub code: Option<u32>,
+    pub name: Option<String>,
+    pub message: String,
+    pub logs: Vec<String>,
+}
+
+impl ShopProgramError {
+    /// Builds the error from the transaction logs, preferring the details anchor
+    /// logs (`Error Code: .. Error Number: .. Error Message: ..`) over `fallback_message`.
+    pub fn from_logs(code: Option<u32>, fallback_message: String, logs: Vec<String>) -> Self {
+        let anchor_error_log = logs.iter().find(|log| log.contains("AnchorError"));
+
+        let name = anchor_error_log
+            .and_then(|log| extract_between(log, "Error Code: ", ". Error Number: "))
+            .map(|name| name.to_string());
+        let code = anchor_error_log
+            .and_then(|log| extract_between(log, "Error Number: ", ". Error Message: "))
+            .and_then(|number| number.parse::<u32>().ok())
+            .or(code);
+        let name = name.or_else(|| {
+            PROGRAM_ERROR_MAPPINGS
+                .iter()
+                .find(|((_, number), _, _)| Some(*number) == code)
+                .map(|((name, _), _, _)| name.to_string())
+        });
+        let message = anchor_error_log
+            .and_then(|log| log.split("Error Message: ").nth(1))
+            .map(|message| message.trim_end_matches('.').to_string())
+            .unwrap_or(fallback_message);
+
+        let logs = logs
+            .into_iter()
+            .filter(|log| log.starts_with("Program log:") || log.contains("failed"))
+            .collect();
+
+        ShopProgramError {
+            code,
+            name,
+            message,
+            logs,
+        }
+    }
+
+    /// The error the program would raise for a good that does not fit in the
+    /// goods account, reported before the transaction is sent.
+    pub fn goods_account_full(message: String) -> Self {
+        let (name, code) = anchor_error!(shop_manager::GoodsAccountFull);
+        ShopProgramError {
+            code: Some(code),
+            name: Some(name.to_string()),
+            message,
+            logs: Vec::new(),
+        }
+    }
+
+    pub fn mapping(&self) -> (StatusCode, &'static str) {
+        PROGRAM_ERROR_MAPPINGS
+            .iter()
+            .find(|((name, _), _, _)| Some(*name) == self.name.as_deref())
+            .or_else(|| {
+                PROGRAM_ERROR_MAPPINGS
+                    .iter()
+                    .find(|((_, number), _, _)| Some(*number) == self.code)
+            })
+            .map(|(_, status, code)| (*status, *code))
+            .unwrap_or((StatusCode::BAD_REQUEST, "PROGRAM_ERROR"))
+    }
+}
+
+fn extract_between<'a>(text: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let (_, rest) = text.split_once(start)?;
+    let (value, _) = rest.split_once(end)?;
+    Some(value)
+}
+
+fn get_transaction_logs(e: &SolanaClientError) -> Vec<String> {
+    match e.kind() {
+        ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            data: RpcResponseErrorData::SendTransactionPreflightFailure(simulation),
+            ..
+        }) => simulation.logs.clone().unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
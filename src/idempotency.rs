@@ -0,0 +1,362 @@
+use super::*;
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::web::Bytes;
+use anchor_client::solana_sdk::signature::Signature;
+use log::warn;
+use serde_json::json;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
+use std::hash::{Hash, Hasher};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Set on responses replayed from the idempotency store.
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+tokio::task_local! {
+    static SUBMITTED_SIGNATURES: Arc<Mutex<Vec<String>>>;
+}
+
+/// Records a transaction sent for the current request, once one is sent the
+/// request is never run again for its `Idempotency-Key`.
+pub fn record_submitted_transaction(signature: &Signature) {
+    let _ = SUBMITTED_SIGNATURES.try_with(|signatures| {
+        lock_signatures(signatures).push(signature.to_string());
+    });
+}
+
+fn lock_signatures(signatures: &Mutex<Vec<String>>) -> MutexGuard<'_, Vec<String>> {
+    signatures.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[derive(Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl StoredResponse {
+    fn to_http_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status);
+        for (name, value) in self.headers.iter() {
+            response.append_header((name.clone(), value.clone()));
+        }
+        response
+            .insert_header((IDEMPOTENT_REPLAYED_HEADER, "true"))
+            .body(self.body.clone())
+    }
+
+    /// Replayed when the first request sent transactions but did not finish,
+    /// their outcome is looked up with `GET /transactions/{signature}`.
+    fn submitted(signatures: Vec<String>) -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        let body = errors::ErrorResponseBody {
+            status: StatusCode::ACCEPTED.as_u16(),
+            code: "TRANSACTIONS_SUBMITTED",
+            reason: "the request did not finish after sending its transactions, check their status"
+                .to_string(),
+            details: json!({ "signatures": signatures }),
+        };
+        StoredResponse {
+            status: StatusCode::ACCEPTED,
+            headers,
+            body: Bytes::from(
+                serde_json::to_vec(&body).expect("the response body is always serializable"),
+            ),
+        }
+    }
+}
+
+enum IdempotencyEntry {
+    /// the first request is still running, it holds the lock until it is done
+    InFlight {
+        fingerprint: u64,
+        lock: Arc<AsyncMutex<()>>,
+    },
+    Completed {
+        fingerprint: u64,
+        response: StoredResponse,
+        stored_at: Instant,
+    },
+}
+
+enum Reservation {
+    Run(InFlightRequest),
+    Wait(Arc<AsyncMutex<()>>),
+    Replay(StoredResponse),
+    KeyReused,
+}
+
+/// An `Idempotency-Key` and the label of the api key it was sent with, clients
+/// can not see or collide with the keys of each other.
+type ScopedKey = (Option<String>, String);
+
+/// Outcomes of the mutating requests sent with an `Idempotency-Key`, shared by
+/// every worker. Outcomes are kept for `ttl`, server errors are only kept once
+/// a transaction was sent, otherwise a retry runs the request again. They are
+/// only kept in memory, a restart forgets them.
+pub struct IdempotencyStore {
+    ttl: Duration,
+    entries: Mutex<HashMap<ScopedKey, IdempotencyEntry>>,
+}
+
+impl IdempotencyStore {
+    pub fn new(ttl: Duration) -> Self {
+        IdempotencyStore {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<ScopedKey, IdempotencyEntry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn reserve(self: &Arc<Self>, key: &ScopedKey, fingerprint: u64) -> Reservation {
+        let mut entries = self.entries();
+        let now = Instant::now();
+        entries.retain(|_, entry| match entry {
+            IdempotencyEntry::Completed { stored_at, .. } => {
+                now.duration_since(*stored_at) < self.ttl
+            }
+            IdempotencyEntry::InFlight { .. } => true,
+        });
+
+        match entries.get(key) {
+            Some(IdempotencyEntry::InFlight {
+                fingerprint: stored_fingerprint,
+                lock,
+            }) if *stored_fingerprint == fingerprint => Reservation::Wait(lock.clone()),
+            Some(IdempotencyEntry::Completed {
+                fingerprint: stored_fingerprint,
+                response,
+                ..
+            }) if *stored_fingerprint == fingerprint => Reservation::Replay(response.clone()),
+            Some(_) => Reservation::KeyReused,
+            None => {
+                let lock = Arc::new(AsyncMutex::new(()));
+                let guard = lock
+                    .clone()
+                    .try_lock_owned()
+                    .expect("a new lock is never held");
+                entries.insert(
+                    key.clone(),
+                    IdempotencyEntry::InFlight { fingerprint, lock },
+                );
+                Reservation::Run(InFlightRequest {
+                    store: self.clone(),
+                    key: key.clone(),
+                    fingerprint,
+                    submitted_signatures: Arc::new(Mutex::new(Vec::new())),
+                    completed: false,
+                    _guard: guard,
+                })
+            }
+        }
+    }
+}
+
+/// The first request for a key, requests waiting on it are released when it is
+/// dropped. A request that never completes (e.g. the client went away) frees
+/// its key so it can be retried, unless it already sent a transaction: retries
+/// then get the signatures rather than sending it again.
+struct InFlightRequest {
+    store: Arc<IdempotencyStore>,
+    key: ScopedKey,
+    fingerprint: u64,
+    submitted_signatures: Arc<Mutex<Vec<String>>>,
+    completed: bool,
+    _guard: OwnedMutexGuard<()>,
+}
+
+impl InFlightRequest {
+    fn complete(mut self, response: StoredResponse) {
+        if response.status.is_server_error() {
+            return;
+        }
+        self.keep(response);
+    }
+
+    fn keep(&mut self, response: StoredResponse) {
+        self.store.entries().insert(
+            self.key.clone(),
+            IdempotencyEntry::Completed {
+                fingerprint: self.fingerprint,
+                response,
+                stored_at: Instant::now(),
+            },
+        );
+        self.completed = true;
+    }
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        if self.completed {
+            return;
+        }
+        let signatures = lock_signatures(&self.submitted_signatures).clone();
+        if signatures.is_empty() {
+            self.store.entries().remove(&self.key);
+        } else {
+            warn!(
+                "the request with Idempotency-Key {} did not finish after sending {signatures:?}",
+                self.key.1
+            );
+            self.keep(StoredResponse::submitted(signatures));
+        }
+    }
+}
+
+/// Honors the `Idempotency-Key` header on POST, PUT, PATCH and DELETE requests:
+/// the first outcome is replayed to retries of the same request, concurrent
+/// retries wait for the first one to finish and reusing a key for another
+/// request is rejected.
+pub struct Idempotency {
+    store: Arc<IdempotencyStore>,
+}
+
+impl Idempotency {
+    pub fn new(store: Arc<IdempotencyStore>) -> Self {
+        Idempotency { store }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Idempotency
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = IdempotencyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdempotencyMiddleware {
+            service: Rc::new(service),
+            store: self.store.clone(),
+        }))
+    }
+}
+
+pub struct IdempotencyMiddleware<S> {
+    service: Rc<S>,
+    store: Arc<IdempotencyStore>,
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotencyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let store = self.store.clone();
+        Box::pin(handle_request(service, store, req))
+    }
+}
+
+async fn handle_request<S, B>(
+    service: Rc<S>,
+    store: Arc<IdempotencyStore>,
+    mut req: ServiceRequest,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody + 'static,
+{
+    let is_mutating = matches!(
+        *req.method(),
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    );
+    let key = match req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        Some(key) if is_mutating => key.to_str().ok().map(str::to_string),
+        _ => return Ok(service.call(req).await?.map_into_boxed_body()),
+    };
+    let key = match key.filter(|key| !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH) {
+        Some(key) => key,
+        None => {
+            return Ok(req.error_response(errors::ShopApiError::Validation {
+                reason: format!(
+                    "Idempotency-Key must be 1 to {MAX_IDEMPOTENCY_KEY_LENGTH} visible ascii characters"
+                ),
+                details: serde_json::Value::Null,
+            }))
+        }
+    };
+
+    // the body is part of the fingerprint, it is put back for the handler
+    let body = req.extract::<Bytes>().await?;
+    let fingerprint = get_fingerprint(&req, &body);
+    req.set_payload(Payload::from(body));
+
+    let scoped_key = (api_keys::current_api_key_label(), key.clone());
+    let in_flight_request = loop {
+        match store.reserve(&scoped_key, fingerprint) {
+            Reservation::Run(in_flight_request) => break in_flight_request,
+            Reservation::Wait(lock) => {
+                debug!("waiting for the first request with Idempotency-Key {key}");
+                drop(lock.lock().await);
+            }
+            Reservation::Replay(response) => {
+                info!("replaying the response stored for Idempotency-Key {key}");
+                return Ok(req.into_response(response.to_http_response()));
+            }
+            Reservation::KeyReused => {
+                return Ok(req.error_response(errors::ShopApiError::IdempotencyKeyReused(key)))
+            }
+        }
+    };
+
+    let submitted_signatures = in_flight_request.submitted_signatures.clone();
+    let (req, res) = SUBMITTED_SIGNATURES
+        .scope(submitted_signatures, service.call(req))
+        .await?
+        .into_parts();
+    let (res, body) = res.into_parts();
+    let body = body::to_bytes(body).await.map_err(|e| {
+        let e: Box<dyn Error> = e.into();
+        errors::ShopApiError::Internal(format!("failed to read the response: {e}"))
+    })?;
+
+    in_flight_request.complete(StoredResponse {
+        status: res.status(),
+        headers: res.headers().clone(),
+        body: body.clone(),
+    });
+    Ok(ServiceResponse::new(
+        req,
+        res.set_body(body).map_into_boxed_body(),
+    ))
+}
+
+/// Tells apart two requests sent with the same key.
+fn get_fingerprint(req: &ServiceRequest, body: &Bytes) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    req.method().as_str().hash(&mut hasher);
+    req.path().hash(&mut hasher);
+    req.query_string().hash(&mut hasher);
+    body.hash(&mut hasher);
+    hasher.finish()
+}
@@ -6,12 +6,9 @@ use actix_web::{
 use anchor_client::{
     solana_sdk::signature::read_keypair_file,
     solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Keypair},
-    Client, Cluster, Program,
+    Cluster,
 };
 use base58::FromBase58;
-#[macro_use]
-extern crate dotenv_codegen;
-
 use dotenv::dotenv;
 use env_logger::Env;
 use lazy_static::lazy_static;
@@ -31,18 +28,22 @@ use std::env;
 
 
 
+mod api_keys;
 mod configure;
 mod entrypoint;
 mod errors;
+mod idempotency;
 mod modals;
 mod routes;
 mod shop_anchor_utils;
 mod shop_solana_utils;
 mod tests;
 
+pub use api_keys::*;
 pub use configure::*;
 pub use entrypoint::*;
 pub use errors::*;
+pub use idempotency::*;
 pub use modals::*;
 pub use routes::*;
 pub use shop_anchor_utils::*;
@@ -53,16 +54,17 @@ type ShopResult<T> = Result<T, Box<dyn Error>>;
 
 
 pub async fn configure_and_start_server()-> std::io::Result<()> {
-    lazy_static! {
-        static ref SHOP_CONFIGURATIONS: ShopConfigurations =
-            match configure::setup_environment_and_get_configurations() {
-                Ok(configurations) => configurations,
-                Err(e) => {
-                    error!("{e:#}");
-                    panic!("{e:#}");
-                }
-            };
-    }
-    entrypoint::start_server(&SHOP_CONFIGURATIONS).await
+    let shop_configurations = match configure::setup_environment_and_get_configurations() {
+        Ok(configurations) => configurations,
+        Err(e) => {
+            error!("{e:#}");
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                e.to_string(),
+            ));
+        }
+    };
+    // the configurations are shared by every worker for the lifetime of the process
+    let shop_configurations: &'static ShopConfigurations = Box::leak(Box::new(shop_configurations));
+    entrypoint::start_server(shop_configurations).await
 }
-
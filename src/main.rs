@@ -3,5 +3,10 @@ use shop_manager_api;
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
+    // `shop-manager-api keys ...` manages the api keys instead of starting the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("keys") {
+        return shop_manager_api::run_api_keys_command(&args[1..]);
+    }
     shop_manager_api::configure_and_start_server().await
 }
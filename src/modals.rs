@@ -1,18 +1,497 @@
  use anchor_client::anchor_lang::prelude::borsh::de;
 
     use super::*;
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
+    use shop_manager::Good;
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex, RwLock};
+    use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
 
     pub struct ShopState<'a> {
         pub shop_configurations: &'a ShopConfigurations,
+        pub program_client: ShopProgramClient,
+        pub goods_account_key_pair: Keypair,
+        /// shared by every worker, see `GoodsAccountLocks`
+        pub goods_account_locks: Arc<GoodsAccountLocks>,
+    }
+
+    /// One lock per goods account, held by the writes checking `If-Match` from
+    /// the read of the goods until their transaction is sent, so two requests
+    /// can not both pass the same precondition.
+    #[derive(Default)]
+    pub struct GoodsAccountLocks {
+        locks: Mutex<HashMap<Pubkey, Arc<AsyncMutex<()>>>>,
+    }
+
+    impl GoodsAccountLocks {
+        pub async fn lock(&self, goods_account: Pubkey) -> OwnedMutexGuard<()> {
+            let lock = self
+                .locks
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(goods_account)
+                .or_default()
+                .clone();
+            lock.lock_owned().await
+        }
     }
     #[derive(Clone)]
     pub struct ShopConfigurations {
         pub host: String,
         pub port: String,
+        pub server: ShopServerConfigurations,
+        pub funding_policy: FundingPolicy,
         pub program_id: String,
         pub cluster: String,
         pub cluster_url: String,
         pub cluster_ws_url: String,
+        /// commitment used when a request does not ask for one
+        pub commitment: CommitmentConfig,
         pub payer_key_pair_bytes: [u8; 64],
         pub account_key_pair_bytes: [u8; 64],
-    }
\ No newline at end of file
+        /// `None` when `ACCOUNT_PUBKEY` is configured, its keypair is never copied
+        pub state_file: Option<String>,
+        /// file the shops created through `POST /shops` are persisted to
+        pub shops_file: String,
+        /// hashes and labels of the api keys clients authenticate with
+        pub api_keys_file: String,
+        pub api_auth_enabled: bool,
+        /// readiness fails when the payer balance is below this
+        pub health_min_balance_lamports: u64,
+        /// how long responses are replayed for a repeated `Idempotency-Key`
+        pub idempotency_ttl_secs: u64,
+    }
+
+    /// Outcome of a transaction sent by the api.
+    #[derive(Serialize, Debug, Clone)]
+    pub struct TransactionReceipt {
+        pub signature: String,
+        pub slot: Option<u64>,
+        /// commitment the transaction reached when the response was built
+        pub commitment: Option<String>,
+        pub fee_lamports: u64,
+        pub explorer_url: String,
+    }
+
+    /// Response of `GET /transactions/{signature}`.
+    #[derive(Serialize)]
+    pub struct TransactionStatusInfo {
+        pub signature: String,
+        pub slot: Option<u64>,
+        /// `None` once the transaction is rooted
+        pub confirmations: Option<usize>,
+        pub commitment: Option<String>,
+        pub succeeded: bool,
+        /// the error clients would have received had the transaction been sent by the api
+        pub error: Option<ErrorResponseBody>,
+        pub block_time: Option<i64>,
+        pub fee_lamports: Option<u64>,
+        pub logs: Vec<String>,
+        pub explorer_url: String,
+    }
+
+    /// Response of every endpoint that sends a transaction.
+    #[derive(Serialize, Debug)]
+    pub struct TransactionEnvelope {
+        pub transaction: TransactionReceipt,
+        pub goods: Vec<Good>,
+    }
+
+    /// Path of the routes of a single good, the `shop_id` of the shop routes is
+    /// read by the `Shop` extractor.
+    #[derive(Deserialize, Debug)]
+    pub struct GoodPath {
+        pub id: u64,
+    }
+
+    /// Query parameters of `GET /goods`.
+    #[derive(Deserialize, Debug, Default)]
+    pub struct GoodsQuery {
+        /// case insensitive substring of the name
+        pub name: Option<String>,
+        pub min_price: Option<u64>,
+        pub max_price: Option<u64>,
+        /// `id`, `name` or `price`
+        pub sort: Option<String>,
+        /// `asc` or `desc`
+        pub order: Option<String>,
+        pub limit: Option<usize>,
+        pub offset: Option<usize>,
+    }
+
+    /// Response of `GET /goods`.
+    #[derive(Serialize, Debug)]
+    pub struct GoodsPage {
+        pub goods: Vec<Good>,
+        /// number of goods matching the filters, across every page
+        pub total: usize,
+        pub limit: usize,
+        pub offset: usize,
+        /// `None` on the last page
+        pub next_offset: Option<usize>,
+    }
+
+    /// Outcome of one good of a batch, goods sent in the same transaction
+    /// share its signature.
+    #[derive(Serialize)]
+    pub struct BatchItemResult {
+        /// position of the good in the request
+        pub index: usize,
+        pub id: u64,
+        pub succeeded: bool,
+        pub signature: Option<String>,
+        pub error: Option<ErrorResponseBody>,
+    }
+
+    /// Response of the `/goods/batch` endpoints.
+    #[derive(Serialize)]
+    pub struct BatchResponse {
+        pub results: Vec<BatchItemResult>,
+        pub transactions: Vec<TransactionReceipt>,
+        pub goods: Vec<Good>,
+    }
+
+    /// Response of `GET /wallet`.
+    #[derive(Serialize, Debug)]
+    pub struct WalletInfo {
+        pub payer: String,
+        pub lamports: u64,
+        pub sol: f64,
+        pub cluster: String,
+        pub goods_account: String,
+        /// `None` until the goods account has been initialized.
+        pub goods_account_rent_exempt_minimum_lamports: Option<u64>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct AirdropRequest {
+        pub sol: Option<f64>,
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct AirdropResponse {
+        pub signature: String,
+        pub lamports: u64,
+        pub balance_lamports: u64,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct FundingPolicy {
+        pub airdrop_enabled: bool,
+        /// the payer is only airdropped when its balance falls below this
+        pub min_balance_lamports: u64,
+        pub airdrop_lamports: u64,
+        pub airdrop_max_retries: u32,
+        pub airdrop_backoff_ms: u64,
+    }
+
+    /// Contents of the state file written after `POST /initialize`.
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct PersistedShopState {
+        pub goods_account: String,
+        pub goods_account_key_pair: Vec<u8>,
+        /// `None` while the shop is pending: its keypair is saved before the
+        /// account is initialized so a failure can not lose a funded account
+        #[serde(default)]
+        pub initialize_signature: Option<String>,
+        /// only set for the shops created through `POST /shops`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub name: Option<String>,
+    }
+
+    impl PersistedShopState {
+        pub fn key_pair_bytes(&self) -> ShopResult<[u8; 64]> {
+            let key_pair_bytes: [u8; 64] =
+                self.goods_account_key_pair.as_slice().try_into().map_err(|_| {
+                    errors::ShopCustomError("state file holds an invalid keypair".to_string())
+                })?;
+            Ok(key_pair_bytes)
+        }
+    }
+
+    /// Shops created through `POST /shops`, shared by every worker and saved to
+    /// the shops file before they are served so they survive restarts.
+    pub struct ShopRegistry {
+        shops_file: String,
+        shops: RwLock<Vec<PersistedShopState>>,
+    }
+
+    impl ShopRegistry {
+        pub fn new(shops_file: String, shops: Vec<PersistedShopState>) -> Self {
+            ShopRegistry {
+                shops_file,
+                shops: RwLock::new(shops),
+            }
+        }
+
+        pub fn contains(&self, goods_account: &str) -> bool {
+            self.shops
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .iter()
+                .any(|shop| {
+                    shop.goods_account == goods_account && shop.initialize_signature.is_some()
+                })
+        }
+
+        pub fn summaries(&self) -> Vec<ShopSummary> {
+            self.shops
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .iter()
+                .filter(|shop| shop.initialize_signature.is_some())
+                .map(|shop| ShopSummary {
+                    shop_id: shop.goods_account.clone(),
+                    name: shop.name.clone(),
+                    goods_account: shop.goods_account.clone(),
+                    default: false,
+                })
+                .collect()
+        }
+
+        /// The shop is only kept once the shops file holds it.
+        pub fn add(&self, shop: PersistedShopState) -> ShopResult<()> {
+            let mut shops = self.shops.write().unwrap_or_else(|e| e.into_inner());
+            shops.push(shop);
+            if let Err(e) = configure::save_persisted_shops(&self.shops_file, &shops) {
+                shops.pop();
+                return Err(e);
+            }
+            Ok(())
+        }
+
+        /// Goods accounts of the shops whose Initialize transaction was not
+        /// recorded, see `entrypoint::reconcile_pending_shops`.
+        pub fn pending_goods_accounts(&self) -> Vec<String> {
+            self.shops
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .iter()
+                .filter(|shop| shop.initialize_signature.is_none())
+                .map(|shop| shop.goods_account.clone())
+                .collect()
+        }
+
+        /// Records the Initialize transaction of a pending shop, which then
+        /// gets served.
+        pub fn mark_initialized(&self, goods_account: &str, signature: &str) -> ShopResult<()> {
+            let mut shops = self.shops.write().unwrap_or_else(|e| e.into_inner());
+            let index = shops
+                .iter()
+                .position(|shop| shop.goods_account == goods_account)
+                .ok_or_else(|| errors::ShopCustomError(format!("no shop {goods_account}")))?;
+            shops[index].initialize_signature = Some(signature.to_string());
+            if let Err(e) = configure::save_persisted_shops(&self.shops_file, &shops) {
+                shops[index].initialize_signature = None;
+                return Err(e);
+            }
+            Ok(())
+        }
+    }
+
+    /// Entry of `GET /shops`, a shop is identified by its goods account address.
+    #[derive(Serialize, Debug, Clone)]
+    pub struct ShopSummary {
+        pub shop_id: String,
+        pub name: Option<String>,
+        pub goods_account: String,
+        /// the configured goods account, also served by the routes outside `/shops`
+        pub default: bool,
+    }
+
+    /// Body of `POST /shops`.
+    #[derive(Deserialize, Debug, Default)]
+    pub struct NewShop {
+        pub name: Option<String>,
+    }
+
+    /// Response of `POST /shops`.
+    #[derive(Serialize, Debug)]
+    pub struct ShopCreated {
+        pub shop: ShopSummary,
+        pub transaction: TransactionReceipt,
+    }
+
+    /// Outcome of a single readiness check.
+    #[derive(Serialize, Debug, Clone)]
+    pub struct HealthCheck {
+        pub name: &'static str,
+        pub healthy: bool,
+        pub message: String,
+        pub details: Value,
+    }
+
+    impl HealthCheck {
+        pub fn passed(name: &'static str, message: String, details: Value) -> Self {
+            HealthCheck {
+                name,
+                healthy: true,
+                message,
+                details,
+            }
+        }
+
+        pub fn failed(name: &'static str, message: String, details: Value) -> Self {
+            HealthCheck {
+                name,
+                healthy: false,
+                message,
+                details,
+            }
+        }
+    }
+
+    /// Response of `GET /health/live` and `GET /health/ready`.
+    #[derive(Serialize, Debug)]
+    pub struct HealthReport {
+        /// `ok` when every check passed, `unavailable` otherwise
+        pub status: &'static str,
+        pub checks: Vec<HealthCheck>,
+    }
+
+    impl HealthReport {
+        pub fn new(checks: Vec<HealthCheck>) -> Self {
+            let status = if checks.iter().all(|check| check.healthy) {
+                "ok"
+            } else {
+                "unavailable"
+            };
+            HealthReport { status, checks }
+        }
+
+        pub fn is_healthy(&self) -> bool {
+            self.status == "ok"
+        }
+    }
+
+    /// Response of `GET /shop`.
+    #[derive(Serialize, Debug)]
+    pub struct ShopInfo {
+        pub goods_account: String,
+        pub owner: Option<String>,
+        pub payer: String,
+        pub program_id: String,
+        pub initialized: bool,
+    }
+
+    /// Entry of the `GET /` route listing.
+    #[derive(Serialize, Debug)]
+    pub struct RouteDescription {
+        pub method: &'static str,
+        pub path: &'static str,
+        pub description: &'static str,
+        pub body: Option<Value>,
+    }
+
+    impl RouteDescription {
+        pub fn new(
+            method: &'static str,
+            path: &'static str,
+            description: &'static str,
+            body: Option<Value>,
+        ) -> Self {
+            RouteDescription {
+                method,
+                path,
+                description,
+                body,
+            }
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct ShopServerConfigurations {
+        pub bind_addresses: Vec<SocketAddr>,
+        pub unix_sockets: Vec<String>,
+        pub workers: Option<usize>,
+        pub backlog: Option<u32>,
+        /// `Some(0)` disables keep-alive, `None` keeps the actix default.
+        pub keep_alive_secs: Option<u64>,
+        pub client_request_timeout_ms: Option<u64>,
+        pub client_disconnect_timeout_ms: Option<u64>,
+    }
+
+    /// Optional configuration file, every value can be overridden from the environment.
+    #[derive(Deserialize, Default, Debug)]
+    #[serde(default, deny_unknown_fields)]
+    pub struct ShopConfigurationsFile {
+        pub host: Option<ConfigurationValue>,
+        pub port: Option<ConfigurationValue>,
+        pub unix_socket: Option<ConfigurationValue>,
+        pub workers: Option<ConfigurationValue>,
+        pub backlog: Option<ConfigurationValue>,
+        pub keep_alive_secs: Option<ConfigurationValue>,
+        pub client_request_timeout_ms: Option<ConfigurationValue>,
+        pub client_disconnect_timeout_ms: Option<ConfigurationValue>,
+        pub program_id: Option<ConfigurationValue>,
+        pub cluster: Option<ConfigurationValue>,
+        pub cluster_url: Option<ConfigurationValue>,
+        pub cluster_ws_url: Option<ConfigurationValue>,
+        pub commitment: Option<ConfigurationValue>,
+        pub payer_key_pair: Option<ConfigurationValue>,
+        pub account_pubkey: Option<ConfigurationValue>,
+        pub allow_ephemeral_keys: Option<ConfigurationValue>,
+        pub state_file: Option<ConfigurationValue>,
+        pub shops_file: Option<ConfigurationValue>,
+        pub api_keys_file: Option<ConfigurationValue>,
+        pub api_auth_disabled: Option<ConfigurationValue>,
+        pub airdrop_enabled: Option<ConfigurationValue>,
+        pub airdrop_min_balance_sol: Option<ConfigurationValue>,
+        pub airdrop_amount_sol: Option<ConfigurationValue>,
+        pub airdrop_max_retries: Option<ConfigurationValue>,
+        pub airdrop_backoff_ms: Option<ConfigurationValue>,
+        pub health_min_balance_sol: Option<ConfigurationValue>,
+        pub idempotency_ttl_secs: Option<ConfigurationValue>,
+    }
+
+    #[derive(Deserialize, Debug, Clone)]
+    #[serde(untagged)]
+    pub enum ConfigurationValue {
+        Text(String),
+        Number(i64),
+        Decimal(f64),
+        Flag(bool),
+    }
+
+    impl ShopConfigurationsFile {
+        pub fn get(&self, key: &str) -> Option<String> {
+            let value = match key {
+                "HOST" => &self.host,
+                "PORT" => &self.port,
+                "UNIX_SOCKET" => &self.unix_socket,
+                "WORKERS" => &self.workers,
+                "BACKLOG" => &self.backlog,
+                "KEEP_ALIVE_SECS" => &self.keep_alive_secs,
+                "CLIENT_REQUEST_TIMEOUT_MS" => &self.client_request_timeout_ms,
+                "CLIENT_DISCONNECT_TIMEOUT_MS" => &self.client_disconnect_timeout_ms,
+                "PROGRAM_ID" => &self.program_id,
+                "CLUSTER" => &self.cluster,
+                "CLUSTER_URL" => &self.cluster_url,
+                "CLUSTER_WS_URL" => &self.cluster_ws_url,
+                "COMMITMENT" => &self.commitment,
+                "PAYER_KEY_PAIR" => &self.payer_key_pair,
+                "ACCOUNT_PUBKEY" => &self.account_pubkey,
+                "ALLOW_EPHEMERAL_KEYS" => &self.allow_ephemeral_keys,
+                "STATE_FILE" => &self.state_file,
+                "SHOPS_FILE" => &self.shops_file,
+                "API_KEYS_FILE" => &self.api_keys_file,
+                "API_AUTH_DISABLED" => &self.api_auth_disabled,
+                "AIRDROP_ENABLED" => &self.airdrop_enabled,
+                "AIRDROP_MIN_BALANCE_SOL" => &self.airdrop_min_balance_sol,
+                "AIRDROP_AMOUNT_SOL" => &self.airdrop_amount_sol,
+                "AIRDROP_MAX_RETRIES" => &self.airdrop_max_retries,
+                "AIRDROP_BACKOFF_MS" => &self.airdrop_backoff_ms,
+                "HEALTH_MIN_BALANCE_SOL" => &self.health_min_balance_sol,
+                "IDEMPOTENCY_TTL_SECS" => &self.idempotency_ttl_secs,
+                _ => &None,
+            };
+            value.as_ref().map(|value| match value {
+                ConfigurationValue::Text(text) => text.clone(),
+                ConfigurationValue::Number(number) => number.to_string(),
+                ConfigurationValue::Decimal(decimal) => decimal.to_string(),
+                ConfigurationValue::Flag(flag) => flag.to_string(),
+            })
+        }
+    }
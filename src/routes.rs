@@ -1,279 +1,1332 @@
 use super::*;
+use actix_web::dev::Payload;
+use actix_web::http::header;
+use actix_web::http::header::{EntityTag, Header, IfMatch};
 use actix_web::web;
+use actix_web::{FromRequest, HttpRequest};
+use actix_web::{delete, put, Scope};
 use actix_web::web::Json;
 use anchor_client::anchor_lang::system_program;
-use anchor_client::anchor_lang::system_program::System;
-use anchor_client::solana_sdk::config::program;
-use anchor_client::solana_sdk::native_token::LAMPORTS_PER_SOL;
+use anchor_client::anchor_lang::InstructionData;
+use anchor_client::solana_sdk::native_token::{lamports_to_sol, sol_to_lamports};
+use anchor_client::solana_sdk::instruction::Instruction;
+use anchor_client::solana_sdk::signature::Signature;
 use anchor_client::solana_sdk::signer::Signer;
 use log::debug;
 use log::info;
+use log::warn;
 use shop_manager::accounts;
 use shop_manager::instruction;
 use shop_manager::Good;
-use shop_manager::GoodsAccount;
 use actix_web::Result;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashSet;
+use serde::de::DeserializeOwned;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::str::FromStr;
+use tokio::sync::OwnedMutexGuard;
+
+/// Header a request can pick its commitment with, responses echo the
+/// commitment they were served at in it.
+pub const COMMITMENT_HEADER: &str = "x-commitment";
+/// Signature of the transaction sent by a request answered without a body.
+pub const TRANSACTION_SIGNATURE_HEADER: &str = "x-transaction-signature";
+
+/// Commitment a request is served at, taken from the `commitment` query
+/// parameter or the `X-Commitment` header, defaulting to the configured one.
+pub struct RequestCommitment(pub CommitmentConfig);
+
+impl RequestCommitment {
+    pub fn name(&self) -> &'static str {
+        shop_solana_utils::get_commitment_level_name(self.0)
+    }
+}
+
+#[derive(Deserialize)]
+struct CommitmentQuery {
+    commitment: Option<String>,
+}
+
+impl FromRequest for RequestCommitment {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(get_request_commitment(req).map_err(actix_web::Error::from))
+    }
+}
+
+fn get_request_commitment(req: &HttpRequest) -> Result<RequestCommitment, errors::ShopApiError> {
+    let requested_commitment = web::Query::<CommitmentQuery>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.into_inner().commitment)
+        .or_else(|| {
+            req.headers()
+                .get(COMMITMENT_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        });
+
+    match requested_commitment {
+        Some(requested_commitment) => shop_solana_utils::parse_commitment(&requested_commitment)
+            .map(RequestCommitment)
+            .ok_or_else(|| errors::ShopApiError::Validation {
+                reason: format!(
+                    "commitment `{requested_commitment}` must be one of processed, confirmed or finalized"
+                ),
+                details: json!({ "commitment": requested_commitment }),
+            }),
+        None => Ok(RequestCommitment(
+            req.app_data::<web::Data<ShopState<'static>>>()
+                .map(|shop_state| shop_state.shop_configurations.commitment)
+                .unwrap_or_default(),
+        )),
+    }
+}
+
+/// Json body that may be left out. Unlike `Option<Json<T>>` a body that is sent
+/// but invalid is rejected with the `JsonConfig` error rather than ignored.
+pub struct OptionalJson<T>(pub Option<T>);
+
+impl<T: DeserializeOwned + 'static> FromRequest for OptionalJson<T> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let headers = req.headers();
+        let has_body = match headers.get(header::CONTENT_LENGTH) {
+            Some(content_length) => content_length
+                .to_str()
+                .map_or(true, |length| length.trim() != "0"),
+            None => headers.contains_key(header::TRANSFER_ENCODING),
+        };
+        if !has_body {
+            return Box::pin(ready(Ok(OptionalJson(None))));
+        }
+        let json = web::Json::<T>::from_request(req, payload);
+        Box::pin(async move { Ok(OptionalJson(Some(json.await?.into_inner()))) })
+    }
+}
+
+/// Longest name a shop created through `POST /shops` may have.
+pub const MAX_SHOP_NAME_LENGTH: usize = 50;
+
+/// Shop a request is served for: the `{shop_id}` of the `/shops/{shop_id}`
+/// routes, the configured goods account on every other route. A shop is
+/// identified by the address of its goods account.
+pub struct Shop {
+    pub goods_account: Pubkey,
+}
+
+impl FromRequest for Shop {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(get_request_shop(req).map_err(actix_web::Error::from))
+    }
+}
+
+fn get_request_shop(req: &HttpRequest) -> Result<Shop, errors::ShopApiError> {
+    let default_goods_account = req
+        .app_data::<web::Data<ShopState<'static>>>()
+        .map(|shop_state| shop_state.goods_account_key_pair.pubkey())
+        .ok_or_else(|| errors::ShopApiError::Internal("the shop state is missing".to_string()))?;
+    let shop_id = match req.match_info().get("shop_id") {
+        Some(shop_id) => shop_id,
+        None => {
+            return Ok(Shop {
+                goods_account: default_goods_account,
+            })
+        }
+    };
+
+    let is_registered = |goods_account: &Pubkey| {
+        *goods_account == default_goods_account
+            || req
+                .app_data::<web::Data<ShopRegistry>>()
+                .map_or(false, |shop_registry| {
+                    shop_registry.contains(&goods_account.to_string())
+                })
+    };
+    Pubkey::from_str(shop_id)
+        .ok()
+        .filter(is_registered)
+        .map(|goods_account| Shop { goods_account })
+        .ok_or_else(|| errors::ShopApiError::ShopNotFound(shop_id.to_string()))
+}
+
+/// Registers every route of the api, used by the server and the tests alike.
+/// Keep `route_descriptions` in sync when adding a route.
+pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    // malformed query parameters and bodies get the same json error body as every other error
+    cfg.app_data(web::QueryConfig::default().error_handler(|e, _| {
+        errors::ShopApiError::Validation {
+            reason: format!("invalid query parameters: {e}"),
+            details: serde_json::Value::Null,
+        }
+        .into()
+    }));
+    cfg.app_data(web::JsonConfig::default().error_handler(|e, _| {
+        errors::ShopApiError::Validation {
+            reason: format!("invalid json body: {e}"),
+            details: serde_json::Value::Null,
+        }
+        .into()
+    }));
+    cfg.service(list_routes)
+        .service(initialize)
+        .service(get_shop)
+        .service(insert_goods)
+        .service(update_goods)
+        .service(delete_goods)
+        .service(delete_all_goods)
+        .service(get_all_goods)
+        .service(get_transaction)
+        .service(goods_scope("/goods"))
+        .service(shops_scope())
+        .service(wallet_scope())
+        .service(health_scope());
+}
+
+pub fn route_descriptions() -> Vec<RouteDescription> {
+    let good =
+        json!({ "id": 1, "name": "Rice", "image": "https://example.com/rice.png", "price": 150 });
+    vec![
+        RouteDescription::new("GET", "/", "list the routes supported by this server", None),
+        RouteDescription::new("POST", "/initialize", "initialize the goods account", None),
+        RouteDescription::new("GET", "/shop", "goods account address and status", None),
+        RouteDescription::new("POST", "/insert_goods", "insert a good", Some(good.clone())),
+        RouteDescription::new("POST", "/update_goods", "update a good", Some(good.clone())),
+        RouteDescription::new(
+            "POST",
+            "/delete_goods",
+            "delete a good by id",
+            Some(good.clone()),
+        ),
+        RouteDescription::new(
+            "POST",
+            "/delete_all_goods",
+            "delete every good",
+            Some(good.clone()),
+        ),
+        RouteDescription::new("POST", "/get_all_goods", "list every good", None),
+        RouteDescription::new(
+            "GET",
+            "/transactions/{signature}",
+            "status, error and logs of a transaction",
+            None,
+        ),
+        RouteDescription::new(
+            "GET",
+            "/goods",
+            "list goods, filtered with name, min_price and max_price, sorted with sort (id, name, price) and order (asc, desc), paged with limit and offset",
+            None,
+        ),
+        RouteDescription::new("POST", "/goods", "create a good", Some(good.clone())),
+        RouteDescription::new("GET", "/goods/{id}", "get a good", None),
+        RouteDescription::new("PUT", "/goods/{id}", "replace a good", Some(good.clone())),
+        RouteDescription::new("DELETE", "/goods/{id}", "delete a good", None),
+        RouteDescription::new(
+            "POST",
+            "/goods/batch/insert",
+            "insert a list of goods in as few transactions as possible",
+            Some(json!([good])),
+        ),
+        RouteDescription::new(
+            "POST",
+            "/goods/batch/update",
+            "update a list of goods in as few transactions as possible",
+            Some(json!([good])),
+        ),
+        RouteDescription::new(
+            "POST",
+            "/goods/batch/delete",
+            "delete a list of goods by id in as few transactions as possible",
+            Some(json!([1, 2])),
+        ),
+        RouteDescription::new("GET", "/shops", "list the shops served by this server", None),
+        RouteDescription::new(
+            "POST",
+            "/shops",
+            "create a shop with a new goods account",
+            Some(json!({ "name": "Corner shop" })),
+        ),
+        RouteDescription::new("GET", "/shops/{shop_id}", "goods account address and status", None),
+        RouteDescription::new("GET", "/shops/{shop_id}/goods", "list the goods of a shop", None),
+        RouteDescription::new(
+            "POST",
+            "/shops/{shop_id}/goods",
+            "create a good in a shop",
+            Some(good.clone()),
+        ),
+        RouteDescription::new("GET", "/shops/{shop_id}/goods/{id}", "get a good of a shop", None),
+        RouteDescription::new(
+            "PUT",
+            "/shops/{shop_id}/goods/{id}",
+            "replace a good of a shop",
+            Some(good.clone()),
+        ),
+        RouteDescription::new(
+            "DELETE",
+            "/shops/{shop_id}/goods/{id}",
+            "delete a good of a shop",
+            None,
+        ),
+        RouteDescription::new(
+            "POST",
+            "/shops/{shop_id}/goods/batch/insert",
+            "insert a list of goods in a shop",
+            Some(json!([good])),
+        ),
+        RouteDescription::new(
+            "POST",
+            "/shops/{shop_id}/goods/batch/update",
+            "update a list of goods of a shop",
+            Some(json!([good])),
+        ),
+        RouteDescription::new(
+            "POST",
+            "/shops/{shop_id}/goods/batch/delete",
+            "delete a list of goods of a shop by id",
+            Some(json!([1, 2])),
+        ),
+        RouteDescription::new(
+            "GET",
+            "/wallet",
+            "payer balance and goods account rent",
+            None,
+        ),
+        RouteDescription::new(
+            "POST",
+            "/wallet/airdrop",
+            "airdrop SOL to the payer, localnet and devnet only",
+            Some(json!({ "sol": 1.0 })),
+        ),
+        RouteDescription::new("GET", "/health/live", "the server is up", None),
+        RouteDescription::new(
+            "GET",
+            "/health/ready",
+            "the cluster, program, goods account and payer balance are usable",
+            None,
+        ),
+    ]
+}
+
+#[get("/")]
+pub async fn list_routes() -> Json<Vec<RouteDescription>> {
+    Json(route_descriptions())
+}
+
 #[post("/initialize")]
-pub async fn initialize(shop_state: web::Data<ShopState<'static>>) -> Result<String> {
-    // Build and send a transaction.
+pub async fn initialize(
+    shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let goods_account_key_pair = &shop_state.goods_account_key_pair;
+    let state_file = shop_state.shop_configurations.state_file.as_deref();
+    let mut persisted_shop_state = PersistedShopState {
+        goods_account: goods_account_key_pair.pubkey().to_string(),
+        goods_account_key_pair: goods_account_key_pair.to_bytes().to_vec(),
+        initialize_signature: None,
+        name: None,
+    };
+    // saved before the account is funded so a failed write can not lose it
+    if let Some(state_file) = state_file {
+        save_shop_state(state_file, &persisted_shop_state)?;
+    }
 
-    // Process each socket concurrently.
-    info!("transactions ongoing...");
+    let transaction =
+        initialize_goods_account(&shop_state, goods_account_key_pair, &commitment).await?;
+    if let Some(state_file) = state_file {
+        persisted_shop_state.initialize_signature = Some(transaction.signature.clone());
+        save_shop_state(state_file, &persisted_shop_state)?;
+    }
 
-    let goods_account_key_pair = shop_solana_utils::keypair_from_bytes(
-        &shop_state.shop_configurations.account_key_pair_bytes,
-    )?;
+    let goods = fetch_shop_goods(&shop_state, &shop, &commitment).await?;
+    Ok(Json(TransactionEnvelope { transaction, goods })
+        .customize()
+        .insert_header((COMMITMENT_HEADER, commitment.name())))
+}
+
+fn save_shop_state(
+    state_file: &str,
+    persisted_shop_state: &PersistedShopState,
+) -> Result<(), errors::ShopApiError> {
+    configure::save_persisted_shop_state(state_file, persisted_shop_state)
+        .map_err(|e| errors::ShopApiError::Internal(format!("failed to persist shop state: {e}")))
+}
 
-    let handle = std::thread::spawn(move || {
-        let program = shop_anchor_utils::try_get_program(shop_state.shop_configurations)
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
-        let payer = program.payer();
+/// Sends the shop_manager `Initialize` instruction, creating the goods account
+/// of `goods_account_key_pair` paid for by the payer.
+async fn initialize_goods_account(
+    shop_state: &ShopState<'static>,
+    goods_account_key_pair: &Keypair,
+    commitment: &RequestCommitment,
+) -> Result<TransactionReceipt, errors::ShopApiError> {
+    info!("transactions ongoing...");
 
-        info!("payer:{payer}");
+    let program_client = &shop_state.program_client;
+    info!("payer:{}", program_client.payer.pubkey());
 
-        let tx = program
-            .request()
-            .accounts(accounts::Initialize {
-                user: program.payer(),
+    let transaction = program_client
+        .send_instruction(
+            accounts::Initialize {
+                user: program_client.payer.pubkey(),
                 system_program: system_program::ID,
                 goods_account: goods_account_key_pair.pubkey(),
-            })
-            .signer(&goods_account_key_pair)
-            .args(instruction::Initialize)
-            .send()
-            .map(|r| return r.to_string())
-            .map_err(|e| errors::ShopCustomError::getCustomError(e));
-
-        return tx;
-    });
-    let tx_id = handle
-        .join()
-        .map_err(|e| errors::ShopCustomError::getCustomError(e))?
-        .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
-
-    let result = format!("transaction signature:{tx_id}");
+            },
+            instruction::Initialize,
+            &[goods_account_key_pair],
+            commitment.0,
+        )
+        .await?;
+
+    let result = format!("transaction signature:{}", transaction.signature);
     info!("{}", result);
-    Ok(tx_id)
+    Ok(transaction)
 }
 
+#[get("/shop")]
+pub async fn get_shop(
+    shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let shop_info = fetch_shop_info(&shop_state, &shop, &commitment).await?;
+    Ok(Json(shop_info)
+        .customize()
+        .insert_header((COMMITMENT_HEADER, commitment.name())))
+}
 
-#[post("/insert_goods")]
-pub async fn insert_goods(
+async fn fetch_shop_info(
+    shop_state: &ShopState<'static>,
+    shop: &Shop,
+    commitment: &RequestCommitment,
+) -> Result<ShopInfo, errors::ShopApiError> {
+    let program_client = &shop_state.program_client;
+
+    let (owner, initialized) = program_client
+        .fetch_goods_account_status(shop.goods_account, commitment.0)
+        .await?;
+
+    Ok(ShopInfo {
+        goods_account: shop.goods_account.to_string(),
+        owner: owner.map(|owner| owner.to_string()),
+        payer: program_client.payer.pubkey().to_string(),
+        program_id: program_client.program_id.to_string(),
+        initialized,
+    })
+}
+
+pub fn shops_scope() -> Scope {
+    web::scope("/shops")
+        .service(list_shops)
+        .service(create_shop)
+        .service(get_shop_by_id)
+        .service(goods_scope("/{shop_id}/goods"))
+}
+
+#[get("")]
+pub async fn list_shops(
     shop_state: web::Data<ShopState<'static>>,
-    good: web::Json<Good>,
-) -> Result<Json<Vec<Good>>> {
-    let good = good.into_inner();
-    info!("good:{good:?}");
-    let goods_account_key_pair = shop_solana_utils::keypair_from_bytes(
-        &shop_state.shop_configurations.account_key_pair_bytes,
-    )?;
+    shop_registry: Option<web::Data<ShopRegistry>>,
+) -> Json<Vec<ShopSummary>> {
+    let goods_account = shop_state.goods_account_key_pair.pubkey().to_string();
+    let mut shops = vec![ShopSummary {
+        shop_id: goods_account.clone(),
+        name: None,
+        goods_account,
+        default: true,
+    }];
+    if let Some(shop_registry) = shop_registry {
+        shops.extend(shop_registry.summaries());
+    }
+    Json(shops)
+}
 
-    info!("transactions ongoing...");
+/// Creates a goods account with a keypair generated and kept by the server.
+#[post("")]
+pub async fn create_shop(
+    shop_state: web::Data<ShopState<'static>>,
+    shop_registry: Option<web::Data<ShopRegistry>>,
+    new_shop: OptionalJson<NewShop>,
+    commitment: RequestCommitment,
+) -> Result<HttpResponse> {
+    let shop_registry = shop_registry.ok_or_else(|| {
+        errors::ShopApiError::Internal("shops can not be created on this server".to_string())
+    })?;
+    let new_shop = new_shop.0.unwrap_or_default();
+    let name = new_shop.name.map(|name| name.trim().to_string());
+    if let Some(name) = &name {
+        let name_length = name.chars().count();
+        if name_length == 0 || name_length > MAX_SHOP_NAME_LENGTH {
+            return Err(errors::ShopApiError::Validation {
+                reason: format!("name must be 1 to {MAX_SHOP_NAME_LENGTH} characters"),
+                details: json!({ "name": name }),
+            }
+            .into());
+        }
+    }
 
-    let handle = std::thread::spawn(move ||->Result<Vec<Good>, ShopCustomError> {
-        let program = shop_anchor_utils::try_get_program(shop_state.shop_configurations)
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+    let goods_account_key_pair = Keypair::new();
+    let goods_account = goods_account_key_pair.pubkey().to_string();
+    shop_registry
+        .add(PersistedShopState {
+            goods_account: goods_account.clone(),
+            goods_account_key_pair: goods_account_key_pair.to_bytes().to_vec(),
+            initialize_signature: None,
+            name: name.clone(),
+        })
+        .map_err(|e| errors::ShopApiError::Internal(format!("failed to persist the shop: {e}")))?;
 
-        let tx = program
-            .request()
-            .accounts(accounts::AddGoods {
-                goods_account: goods_account_key_pair.pubkey(),
+    let transaction =
+        match initialize_goods_account(&shop_state, &goods_account_key_pair, &commitment).await {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                warn!("shop {goods_account} is left pending in the shops file: {e}");
+                return Err(e.into());
+            }
+        };
+    shop_registry
+        .mark_initialized(&goods_account, &transaction.signature)
+        .map_err(|e| errors::ShopApiError::Internal(format!("failed to persist the shop: {e}")))?;
+    info!("shop {goods_account} created");
+
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("/shops/{goods_account}")))
+        .insert_header((COMMITMENT_HEADER, commitment.name()))
+        .json(ShopCreated {
+            shop: ShopSummary {
+                shop_id: goods_account.clone(),
+                name,
+                goods_account,
+                default: false,
+            },
+            transaction,
+        }))
+}
+
+#[get("/{shop_id}")]
+pub async fn get_shop_by_id(
+    shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let shop_info = fetch_shop_info(&shop_state, &shop, &commitment).await?;
+    Ok(Json(shop_info)
+        .customize()
+        .insert_header((COMMITMENT_HEADER, commitment.name())))
+}
+
+/// Goods held by the shop's goods account.
+async fn fetch_shop_goods(
+    shop_state: &ShopState<'static>,
+    shop: &Shop,
+    commitment: &RequestCommitment,
+) -> Result<Vec<Good>, errors::ShopApiError> {
+    shop_state
+        .program_client
+        .fetch_goods(shop.goods_account, commitment.0)
+        .await
+}
+
+async fn fetch_shop_good(
+    shop_state: &ShopState<'static>,
+    shop: &Shop,
+    id: u64,
+    commitment: &RequestCommitment,
+) -> Result<Good, errors::ShopApiError> {
+    let goods = fetch_shop_goods(shop_state, shop, commitment).await?;
+    shop_anchor_utils::find_good_by_id(&goods, id).ok_or(errors::ShopApiError::GoodNotFound(id))
+}
+
+/// Fails with 412 unless the request's `If-Match` matches `etag`, requests
+/// without `If-Match` always pass. The api checks it before sending the
+/// transaction, the program itself knows nothing of entity tags.
+pub fn check_if_match(req: &HttpRequest, etag: &EntityTag) -> Result<(), errors::ShopApiError> {
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return Ok(());
+    }
+    let matches = match IfMatch::parse(req).ok() {
+        Some(IfMatch::Any) => true,
+        Some(IfMatch::Items(etags)) => etags.iter().any(|if_match| if_match.strong_eq(etag)),
+        None => {
+            return Err(errors::ShopApiError::Validation {
+                reason: "If-Match must be `*` or a list of entity tags".to_string(),
+                details: serde_json::Value::Null,
             })
-            .args(instruction::InsertGoods { good: good.clone() })
-            .send()
-            .map(|r| return r.to_string())
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+        }
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(errors::ShopApiError::PreconditionFailed(etag.to_string()))
+    }
+}
 
-        let goods_account: GoodsAccount = program
-            .account(goods_account_key_pair.pubkey())
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+/// Checks `If-Match` against the current state of a good, requests without
+/// the header always pass.
+fn check_good_if_match(
+    req: &HttpRequest,
+    goods: &[Good],
+    id: u64,
+) -> Result<(), errors::ShopApiError> {
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return Ok(());
+    }
+    let current_good = shop_anchor_utils::find_good_by_id(goods, id)
+        .ok_or(errors::ShopApiError::GoodNotFound(id))?;
+    check_if_match(req, &shop_anchor_utils::get_good_etag(&current_good))
+}
 
+/// Takes the lock of the shop's goods account and reads its goods, the lock is
+/// to be held until the transaction is sent so no other write lands between
+/// the precondition checked on the goods and the transaction. The read is made
+/// at `processed` to see the transactions of the writes that held it before.
+async fn lock_goods_account(
+    shop_state: &ShopState<'static>,
+    shop: &Shop,
+) -> Result<(OwnedMutexGuard<()>, shop_anchor_utils::GoodsAccountSpace), errors::ShopApiError> {
+    let goods_account_lock = shop_state
+        .goods_account_locks
+        .lock(shop.goods_account)
+        .await;
+    let goods_space = shop_state
+        .program_client
+        .fetch_goods_account_space(shop.goods_account, CommitmentConfig::processed())
+        .await?;
+    Ok((goods_account_lock, goods_space))
+}
 
-        info!("goods_account: {goods_account:#?}");
-        info!("tx_id:{tx:?}");
-        let goods = goods_account.goods;
+/// Fails before any fee is spent when the goods account has no room left for
+/// `good`, either added or in place of the good with its id.
+async fn check_good_fits(
+    shop_state: &ShopState<'static>,
+    shop: &Shop,
+    good: &Good,
+    commitment: &RequestCommitment,
+) -> Result<(), errors::ShopApiError> {
+    shop_state
+        .program_client
+        .fetch_goods_account_space(shop.goods_account, commitment.0)
+        .await?
+        .try_put(good)
+}
 
-        return Ok(goods);
-    });
-    let goods = handle
-        .join()
-        .map_err(|e| errors::ShopCustomError::getCustomError(e))??;
+/// `ETag` header of a good as it is after a transaction, if it still exists.
+fn updated_good_etag(envelope: &TransactionEnvelope, id: u64) -> Option<header::ETag> {
+    shop_anchor_utils::find_good_by_id(&envelope.goods, id)
+        .map(|good| header::ETag(shop_anchor_utils::get_good_etag(&good)))
+}
 
+/// Instruction of the shop_manager program on the shop's goods account.
+fn goods_instruction(
+    shop_state: &ShopState<'static>,
+    shop: &Shop,
+    args: impl InstructionData,
+) -> Instruction {
+    shop_state.program_client.instruction(
+        accounts::AddGoods {
+            goods_account: shop.goods_account,
+        },
+        args,
+    )
+}
 
-    Ok(Json(goods))
+/// Sends a goods instruction for the shop's goods account and returns the
+/// transaction receipt along with the goods the transaction resulted in.
+async fn send_goods_instruction(
+    shop_state: &ShopState<'static>,
+    shop: &Shop,
+    args: impl InstructionData,
+    commitment: &RequestCommitment,
+) -> Result<TransactionEnvelope, errors::ShopApiError> {
+    info!("transactions ongoing...");
+
+    let transaction = shop_state
+        .program_client
+        .send_instructions(
+            &[goods_instruction(shop_state, shop, args)],
+            &[],
+            commitment.0,
+        )
+        .await?;
+    info!("tx_id:{}", transaction.signature);
+
+    let goods = fetch_shop_goods(shop_state, shop, commitment).await?;
+
+    Ok(TransactionEnvelope { transaction, goods })
 }
 
+#[post("/insert_goods")]
+pub async fn insert_goods(
+    shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
+    good: web::Json<Good>,
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let good = good.into_inner();
+    info!("good:{good:?}");
+    shop_anchor_utils::validate_good(&good)?;
+    check_good_fits(&shop_state, &shop, &good, &commitment).await?;
 
+    let envelope = send_goods_instruction(
+        &shop_state,
+        &shop,
+        instruction::InsertGoods { good },
+        &commitment,
+    )
+    .await?;
+    Ok(Json(envelope)
+        .customize()
+        .insert_header((COMMITMENT_HEADER, commitment.name())))
+}
 
 #[post("/update_goods")]
 pub async fn update_goods(
+    req: HttpRequest,
     shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
     good: web::Json<Good>,
-) -> Result<Json<Vec<Good>>> {
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
     let good = good.into_inner();
     info!("good:{good:?}");
-    let goods_account_key_pair = shop_solana_utils::keypair_from_bytes(
-        &shop_state.shop_configurations.account_key_pair_bytes,
-    )?;
+    shop_anchor_utils::validate_good(&good)?;
+    let id = good.id as u64;
+    let (_goods_account_lock, mut goods_space) = lock_goods_account(&shop_state, &shop).await?;
+    check_good_if_match(&req, goods_space.goods(), id)?;
+    goods_space.try_put(&good)?;
 
-    info!("transactions ongoing...");
+    let envelope = send_goods_instruction(
+        &shop_state,
+        &shop,
+        instruction::UpdateGoods { good },
+        &commitment,
+    )
+    .await?;
+    let mut response = HttpResponse::Ok();
+    if let Some(etag) = updated_good_etag(&envelope, id) {
+        response.insert_header(etag);
+    }
+    Ok(response
+        .insert_header((COMMITMENT_HEADER, commitment.name()))
+        .json(envelope))
+}
 
-    let handle = std::thread::spawn(move ||->Result<Vec<Good>, ShopCustomError> {
-        let program = shop_anchor_utils::try_get_program(shop_state.shop_configurations)
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+#[post("/delete_goods")]
+pub async fn delete_goods(
+    req: HttpRequest,
+    shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
+    good: web::Json<Good>,
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let good = good.into_inner();
+    info!("good:{good:?}");
+    let (_goods_account_lock, goods_space) = lock_goods_account(&shop_state, &shop).await?;
+    check_good_if_match(&req, goods_space.goods(), good.id as u64)?;
 
-        let tx = program
-            .request()
-            .accounts(accounts::AddGoods {
-                goods_account: goods_account_key_pair.pubkey(),
-            })
-            .args(instruction::UpdateGoods { good: good.clone() })
-            .send()
-            .map(|r| return r.to_string())
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+    let envelope = send_goods_instruction(
+        &shop_state,
+        &shop,
+        instruction::DeleteGoods { good_id: good.id },
+        &commitment,
+    )
+    .await?;
+    Ok(Json(envelope)
+        .customize()
+        .insert_header((COMMITMENT_HEADER, commitment.name())))
+}
+
+#[post("/delete_all_goods")]
+pub async fn delete_all_goods(
+    req: HttpRequest,
+    shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
+    good: web::Json<Good>,
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let good = good.into_inner();
+    info!("good:{good:?}");
+    let (_goods_account_lock, goods_space) = lock_goods_account(&shop_state, &shop).await?;
+    check_if_match(&req, &shop_anchor_utils::get_goods_etag(goods_space.goods()))?;
+
+    let envelope =
+        send_goods_instruction(&shop_state, &shop, instruction::DeleteAllGoods, &commitment)
+            .await?;
+    Ok(Json(envelope)
+        .customize()
+        .insert_header((COMMITMENT_HEADER, commitment.name())))
+}
+
+#[post("/get_all_goods")]
+pub async fn get_all_goods(
+    shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
+    // the body used to be required, it is still accepted and ignored
+    _good: Option<web::Json<Good>>,
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let goods = fetch_shop_goods(&shop_state, &shop, &commitment).await?;
+    let etag = shop_anchor_utils::get_goods_etag(&goods);
 
-        let goods_account: GoodsAccount = program
-            .account(goods_account_key_pair.pubkey())
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+    Ok(Json(goods)
+        .customize()
+        .insert_header(header::ETag(etag))
+        .insert_header((COMMITMENT_HEADER, commitment.name())))
+}
 
+#[get("/transactions/{signature}")]
+pub async fn get_transaction(
+    shop_state: web::Data<ShopState<'static>>,
+    signature: web::Path<String>,
+) -> Result<Json<TransactionStatusInfo>> {
+    let signature = signature.into_inner();
+    let signature =
+        Signature::from_str(&signature).map_err(|e| errors::ShopApiError::Validation {
+            reason: format!("invalid transaction signature: {e}"),
+            details: json!({ "signature": signature }),
+        })?;
 
-        info!("goods_account: {goods_account:#?}");
-        info!("tx_id:{tx:?}");
-        let goods = goods_account.goods;
+    let transaction_status = shop_state
+        .program_client
+        .fetch_transaction_status(signature)
+        .await?;
+    Ok(Json(transaction_status))
+}
 
-        return Ok(goods);
-    });
-    let goods = handle
-        .join()
-        .map_err(|e| errors::ShopCustomError::getCustomError(e))??;
+/// Goods routes of a shop, mounted on `/goods` for the configured goods account
+/// and on `/shops/{shop_id}/goods` for every shop.
+pub fn goods_scope(path: &str) -> Scope {
+    web::scope(path)
+        .service(list_goods)
+        .service(create_good)
+        .service(get_good)
+        .service(replace_good)
+        .service(remove_good)
+        .service(batch_insert_goods)
+        .service(batch_update_goods)
+        .service(batch_delete_goods)
+}
 
+#[get("")]
+pub async fn list_goods(
+    shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
+    goods_query: web::Query<GoodsQuery>,
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let goods = fetch_shop_goods(&shop_state, &shop, &commitment).await?;
+    // the tag covers every good, not only the page, so it can guard a delete of all goods
+    let etag = shop_anchor_utils::get_goods_etag(&goods);
+    let goods_page = shop_anchor_utils::query_goods(goods, &goods_query)?;
 
-    Ok(Json(goods))
+    Ok(Json(goods_page)
+        .customize()
+        .insert_header(header::ETag(etag))
+        .insert_header((COMMITMENT_HEADER, commitment.name())))
 }
 
-#[post("/delete_goods")]
-pub async fn delete_goods(
+#[post("")]
+pub async fn create_good(
+    req: HttpRequest,
     shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
     good: web::Json<Good>,
-) -> Result<Json<Vec<Good>>> {
+    commitment: RequestCommitment,
+) -> Result<HttpResponse> {
     let good = good.into_inner();
     info!("good:{good:?}");
-    let goods_account_key_pair = shop_solana_utils::keypair_from_bytes(
-        &shop_state.shop_configurations.account_key_pair_bytes,
-    )?;
+    shop_anchor_utils::validate_good(&good)?;
+    // the good lives under whichever goods route it was created on
+    let location = format!("{}/{}", req.path(), good.id);
+    check_good_fits(&shop_state, &shop, &good, &commitment).await?;
 
-    info!("transactions ongoing...");
+    let envelope = send_goods_instruction(
+        &shop_state,
+        &shop,
+        instruction::InsertGoods { good },
+        &commitment,
+    )
+    .await?;
 
-    let handle = std::thread::spawn(move ||->Result<Vec<Good>, ShopCustomError> {
-        let program = shop_anchor_utils::try_get_program(shop_state.shop_configurations)
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, location))
+        .insert_header((COMMITMENT_HEADER, commitment.name()))
+        .json(envelope))
+}
 
-        let tx = program
-            .request()
-            .accounts(accounts::AddGoods {
-                goods_account: goods_account_key_pair.pubkey(),
-            })
-            .args(instruction::DeleteGoods { good_id: good.id })
-            .send()
-            .map(|r| return r.to_string())
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+#[get("/{id}")]
+pub async fn get_good(
+    shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
+    good_path: web::Path<GoodPath>,
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let id = good_path.id;
+    let good = fetch_shop_good(&shop_state, &shop, id, &commitment).await?;
+    let etag = shop_anchor_utils::get_good_etag(&good);
+
+    Ok(Json(good)
+        .customize()
+        .insert_header(header::ETag(etag))
+        .insert_header((COMMITMENT_HEADER, commitment.name())))
+}
+
+#[put("/{id}")]
+pub async fn replace_good(
+    req: HttpRequest,
+    shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
+    good_path: web::Path<GoodPath>,
+    good: web::Json<Good>,
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let id = good_path.id;
+    let mut good = good.into_inner();
+    // the path is the source of truth for which good gets updated
+    good.id = id as _;
+    info!("good:{good:?}");
+    shop_anchor_utils::validate_good(&good)?;
 
-        let goods_account: GoodsAccount = program
-            .account(goods_account_key_pair.pubkey())
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+    let (_goods_account_lock, mut goods_space) = lock_goods_account(&shop_state, &shop).await?;
+    let current_good = shop_anchor_utils::find_good_by_id(goods_space.goods(), id)
+        .ok_or(errors::ShopApiError::GoodNotFound(id))?;
+    check_if_match(&req, &shop_anchor_utils::get_good_etag(&current_good))?;
+    goods_space.try_put(&good)?;
 
+    let envelope = send_goods_instruction(
+        &shop_state,
+        &shop,
+        instruction::UpdateGoods { good },
+        &commitment,
+    )
+    .await?;
+    let mut response = HttpResponse::Ok();
+    if let Some(etag) = updated_good_etag(&envelope, id) {
+        response.insert_header(etag);
+    }
+    Ok(response
+        .insert_header((COMMITMENT_HEADER, commitment.name()))
+        .json(envelope))
+}
+
+#[delete("/{id}")]
+pub async fn remove_good(
+    req: HttpRequest,
+    shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
+    good_path: web::Path<GoodPath>,
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let id = good_path.id;
 
-        info!("goods_account: {goods_account:#?}");
-        info!("tx_id:{tx:?}");
-        let goods = goods_account.goods;
+    let (_goods_account_lock, goods_space) = lock_goods_account(&shop_state, &shop).await?;
+    let current_good = shop_anchor_utils::find_good_by_id(goods_space.goods(), id)
+        .ok_or(errors::ShopApiError::GoodNotFound(id))?;
+    check_if_match(&req, &shop_anchor_utils::get_good_etag(&current_good))?;
 
-        return Ok(goods);
-    });
-    let goods = handle
-        .join()
-        .map_err(|e| errors::ShopCustomError::getCustomError(e))??;
+    let envelope = send_goods_instruction(
+        &shop_state,
+        &shop,
+        instruction::DeleteGoods { good_id: id as _ },
+        &commitment,
+    )
+    .await?;
+    // the Android client expects 204, the receipt travels in the headers
+    Ok(HttpResponse::NoContent()
+        .insert_header((TRANSACTION_SIGNATURE_HEADER, envelope.transaction.signature))
+        .insert_header((COMMITMENT_HEADER, commitment.name()))
+        .finish())
+}
 
+/// Most goods a single batch request may hold.
+pub const MAX_BATCH_SIZE: usize = 100;
 
-    Ok(Json(goods))
+fn validate_batch_size(batch_size: usize) -> Result<(), errors::ShopApiError> {
+    if batch_size == 0 || batch_size > MAX_BATCH_SIZE {
+        return Err(errors::ShopApiError::Validation {
+            reason: format!("a batch must hold between 1 and {MAX_BATCH_SIZE} goods"),
+            details: json!({ "batch_size": batch_size }),
+        });
+    }
+    Ok(())
 }
 
+fn duplicate_in_batch(id: u64) -> errors::ShopApiError {
+    errors::ShopApiError::Validation {
+        reason: format!("good {id} appears more than once in the batch"),
+        details: json!({ "id": id }),
+    }
+}
 
+/// Sends the goods of a batch that passed validation packed in as few
+/// transactions as possible. A transaction that fails is retried one good at a
+/// time so a single bad good does not fail the goods packed with it.
+async fn send_goods_batch(
+    shop_state: &ShopState<'static>,
+    shop: &Shop,
+    items: Vec<(u64, Result<Instruction, errors::ShopApiError>)>,
+    commitment: &RequestCommitment,
+) -> Result<BatchResponse, errors::ShopApiError> {
+    let program_client = &shop_state.program_client;
 
-#[post("/delete_all_goods")]
-pub async fn delete_all_goods(
+    let mut results = Vec::with_capacity(items.len());
+    let mut pending = Vec::new();
+    for (index, (id, instruction)) in items.into_iter().enumerate() {
+        let error = match instruction {
+            Ok(instruction) => {
+                pending.push((index, instruction));
+                None
+            }
+            Err(e) => Some(e.to_response_body()),
+        };
+        results.push(BatchItemResult {
+            index,
+            id,
+            succeeded: false,
+            signature: None,
+            error,
+        });
+    }
+
+    let mut transactions = Vec::new();
+    for group in program_client.pack_instructions(pending) {
+        let instructions: Vec<Instruction> = group
+            .iter()
+            .map(|(_, instruction)| instruction.clone())
+            .collect();
+        info!("sending a batch transaction of {} goods", group.len());
+
+        let outcomes = match program_client
+            .try_send_instructions(&instructions, &[], commitment.0)
+            .await
+        {
+            Ok(transaction) => {
+                let signature = transaction.signature.clone();
+                transactions.push(transaction);
+                group
+                    .into_iter()
+                    .map(|(index, _)| (index, Ok(signature.clone())))
+                    .collect()
+            }
+            // only a transaction refused on chain surely left its goods untouched,
+            // any other failure may have landed and is reported with its signature
+            Err(failure) if group.len() == 1 || !failure.is_on_chain() => {
+                let failure = get_batch_failure(failure);
+                group
+                    .into_iter()
+                    .map(|(index, _)| (index, Err(failure.clone())))
+                    .collect()
+            }
+            Err(failure) => {
+                warn!(
+                    "batch transaction of {} goods failed, retrying them one by one: {}",
+                    group.len(),
+                    failure.error
+                );
+                let mut outcomes = Vec::with_capacity(group.len());
+                for (index, instruction) in group {
+                    let outcome = program_client
+                        .try_send_instructions(&[instruction], &[], commitment.0)
+                        .await
+                        .map(|transaction| {
+                            let signature = transaction.signature.clone();
+                            transactions.push(transaction);
+                            signature
+                        })
+                        .map_err(get_batch_failure);
+                    outcomes.push((index, outcome));
+                }
+                outcomes
+            }
+        };
+
+        for (index, outcome) in outcomes {
+            let result: &mut BatchItemResult = &mut results[index];
+            match outcome {
+                Ok(signature) => {
+                    result.succeeded = true;
+                    result.signature = Some(signature);
+                }
+                Err((signature, error)) => {
+                    result.signature = signature;
+                    result.error = Some(error);
+                }
+            }
+        }
+    }
+
+    let goods = fetch_shop_goods(shop_state, shop, commitment).await?;
+    Ok(BatchResponse {
+        results,
+        transactions,
+        goods,
+    })
+}
+
+/// Signature and error body reported for each good of a failed transaction.
+fn get_batch_failure(
+    failure: shop_anchor_utils::TransactionFailure,
+) -> (Option<String>, errors::ErrorResponseBody) {
+    (
+        failure.signature.map(|signature| signature.to_string()),
+        failure.error.to_response_body(),
+    )
+}
+
+#[post("/batch/insert")]
+pub async fn batch_insert_goods(
     shop_state: web::Data<ShopState<'static>>,
-    good: web::Json<Good>,
-) -> Result<Json<Vec<Good>>> {
-    let good = good.into_inner();
-    info!("good:{good:?}");
-    let goods_account_key_pair = shop_solana_utils::keypair_from_bytes(
-        &shop_state.shop_configurations.account_key_pair_bytes,
-    )?;
+    shop: Shop,
+    goods: web::Json<Vec<Good>>,
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let goods = goods.into_inner();
+    validate_batch_size(goods.len())?;
 
-    info!("transactions ongoing...");
+    // the goods of the batch take up the account's space one after the other
+    let mut goods_space = shop_state
+        .program_client
+        .fetch_goods_account_space(shop.goods_account, commitment.0)
+        .await?;
+    let mut batch_ids = HashSet::new();
+    let items = goods
+        .into_iter()
+        .map(|good| {
+            let id = good.id as u64;
+            let instruction = if let Err(e) = shop_anchor_utils::validate_good(&good) {
+                Err(e)
+            } else if !batch_ids.insert(id) {
+                Err(duplicate_in_batch(id))
+            } else if shop_anchor_utils::find_good_by_id(goods_space.goods(), id).is_some() {
+                Err(errors::ShopApiError::GoodAlreadyExists(id))
+            } else if let Err(e) = goods_space.try_put(&good) {
+                Err(e)
+            } else {
+                Ok(goods_instruction(
+                    &shop_state,
+                    &shop,
+                    instruction::InsertGoods { good },
+                ))
+            };
+            (id, instruction)
+        })
+        .collect();
 
-    let handle = std::thread::spawn(move ||->Result<Vec<Good>, ShopCustomError> {
-        let program = shop_anchor_utils::try_get_program(shop_state.shop_configurations)
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+    let batch_response = send_goods_batch(&shop_state, &shop, items, &commitment).await?;
+    Ok(Json(batch_response)
+        .customize()
+        .insert_header((COMMITMENT_HEADER, commitment.name())))
+}
 
-        let tx = program
-            .request()
-            .accounts(accounts::AddGoods {
-                goods_account: goods_account_key_pair.pubkey(),
-            })
-            .args(instruction::DeleteAllGoods)
-            .send()
-            .map(|r| return r.to_string())
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+#[post("/batch/update")]
+pub async fn batch_update_goods(
+    shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
+    goods: web::Json<Vec<Good>>,
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let goods = goods.into_inner();
+    validate_batch_size(goods.len())?;
 
-        let goods_account: GoodsAccount = program
-            .account(goods_account_key_pair.pubkey())
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+    // the goods of the batch take up the account's space one after the other
+    let mut goods_space = shop_state
+        .program_client
+        .fetch_goods_account_space(shop.goods_account, commitment.0)
+        .await?;
+    let mut batch_ids = HashSet::new();
+    let items = goods
+        .into_iter()
+        .map(|good| {
+            let id = good.id as u64;
+            let instruction = if let Err(e) = shop_anchor_utils::validate_good(&good) {
+                Err(e)
+            } else if !batch_ids.insert(id) {
+                Err(duplicate_in_batch(id))
+            } else if shop_anchor_utils::find_good_by_id(goods_space.goods(), id).is_none() {
+                Err(errors::ShopApiError::GoodNotFound(id))
+            } else if let Err(e) = goods_space.try_put(&good) {
+                Err(e)
+            } else {
+                Ok(goods_instruction(
+                    &shop_state,
+                    &shop,
+                    instruction::UpdateGoods { good },
+                ))
+            };
+            (id, instruction)
+        })
+        .collect();
 
+    let batch_response = send_goods_batch(&shop_state, &shop, items, &commitment).await?;
+    Ok(Json(batch_response)
+        .customize()
+        .insert_header((COMMITMENT_HEADER, commitment.name())))
+}
 
-        info!("goods_account: {goods_account:#?}");
-        info!("tx_id:{tx:?}");
-        let goods = goods_account.goods;
+#[post("/batch/delete")]
+pub async fn batch_delete_goods(
+    shop_state: web::Data<ShopState<'static>>,
+    shop: Shop,
+    ids: web::Json<Vec<u64>>,
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let ids = ids.into_inner();
+    validate_batch_size(ids.len())?;
 
-        return Ok(goods);
-    });
-    let goods = handle
-        .join()
-        .map_err(|e| errors::ShopCustomError::getCustomError(e))??;
+    let existing_goods = fetch_shop_goods(&shop_state, &shop, &commitment).await?;
+    let mut batch_ids = HashSet::new();
+    let items = ids
+        .into_iter()
+        .map(|id| {
+            let instruction = if shop_anchor_utils::find_good_by_id(&existing_goods, id).is_none() {
+                Err(errors::ShopApiError::GoodNotFound(id))
+            } else if !batch_ids.insert(id) {
+                Err(duplicate_in_batch(id))
+            } else {
+                Ok(goods_instruction(
+                    &shop_state,
+                    &shop,
+                    instruction::DeleteGoods { good_id: id as _ },
+                ))
+            };
+            (id, instruction)
+        })
+        .collect();
 
+    let batch_response = send_goods_batch(&shop_state, &shop, items, &commitment).await?;
+    Ok(Json(batch_response)
+        .customize()
+        .insert_header((COMMITMENT_HEADER, commitment.name())))
+}
 
-    Ok(Json(goods))
+pub fn wallet_scope() -> Scope {
+    web::scope("/wallet")
+        .service(get_wallet)
+        .service(airdrop_to_wallet)
 }
-#[post("/get_all_goods")]
-pub async fn get_all_goods(
+
+#[get("")]
+pub async fn get_wallet(
     shop_state: web::Data<ShopState<'static>>,
-    good: web::Json<Good>,
-) -> Result<Json<Vec<Good>>> {
-    let good = good.into_inner();
-    info!("good:{good:?}");
-    let goods_account_key_pair = shop_solana_utils::keypair_from_bytes(
-        &shop_state.shop_configurations.account_key_pair_bytes,
-    )?;
+    commitment: RequestCommitment,
+) -> Result<impl Responder> {
+    let program_client = &shop_state.program_client;
+    let payer = program_client.payer.pubkey();
+    let goods_account_pubkey = shop_state.goods_account_key_pair.pubkey();
 
-    info!("transactions ongoing...");
+    let lamports = program_client
+        .rpc
+        .get_balance_with_commitment(&payer, commitment.0)
+        .await
+        .map(|response| response.value)
+        .map_err(errors::ShopApiError::from)?;
+
+    let goods_account = program_client
+        .rpc
+        .get_account_with_commitment(&goods_account_pubkey, commitment.0)
+        .await
+        .map_err(errors::ShopApiError::from)?
+        .value;
+    let goods_account_rent_exempt_minimum_lamports = match goods_account {
+        Some(goods_account) => Some(
+            program_client
+                .rpc
+                .get_minimum_balance_for_rent_exemption(goods_account.data.len())
+                .await
+                .map_err(errors::ShopApiError::from)?,
+        ),
+        None => None,
+    };
+
+    Ok(Json(WalletInfo {
+        payer: payer.to_string(),
+        lamports,
+        sol: lamports_to_sol(lamports),
+        cluster: shop_state.shop_configurations.cluster.clone(),
+        goods_account: goods_account_pubkey.to_string(),
+        goods_account_rent_exempt_minimum_lamports,
+    })
+    .customize()
+    .insert_header((COMMITMENT_HEADER, commitment.name())))
+}
+
+#[post("/airdrop")]
+pub async fn airdrop_to_wallet(
+    shop_state: web::Data<ShopState<'static>>,
+    airdrop_request: OptionalJson<AirdropRequest>,
+) -> Result<Json<AirdropResponse>> {
+    let shop_configurations = shop_state.shop_configurations;
+    let funding_policy = &shop_configurations.funding_policy;
 
-    let handle = std::thread::spawn(move ||->Result<Vec<Good>, ShopCustomError> {
-        let program = shop_anchor_utils::try_get_program(shop_state.shop_configurations)
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+    let cluster = shop_anchor_utils::get_cluster(shop_configurations);
+    if !shop_solana_utils::is_airdrop_supported(&cluster) {
+        return Err(errors::ShopApiError::Validation {
+            reason: format!("airdrops are only available on localnet and devnet, not {cluster}"),
+            details: json!({ "cluster": cluster.to_string() }),
+        }
+        .into());
+    }
 
-        let goods_account: GoodsAccount = program
-            .account(goods_account_key_pair.pubkey())
-            .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+    let max_sol = shop_solana_utils::MAX_AIRDROP_SOL;
+    let lamports = match airdrop_request.0.and_then(|airdrop_request| airdrop_request.sol) {
+        Some(sol) if sol <= 0.0 || sol > max_sol => {
+            return Err(errors::ShopApiError::Validation {
+                reason: format!("sol must be greater than 0 and at most {max_sol}"),
+                details: json!({ "sol": sol }),
+            }
+            .into())
+        }
+        Some(sol) => sol_to_lamports(sol),
+        None => funding_policy.airdrop_lamports,
+    };
 
-        let goods = goods_account.goods;
+    let program_client = &shop_state.program_client;
+    let signature =
+        shop_solana_utils::request_airdrop_with_retries(program_client, lamports, funding_policy)
+            .await?;
+    let balance_lamports = program_client
+        .rpc
+        .get_balance(&program_client.payer.pubkey())
+        .await
+        .map_err(errors::ShopApiError::from)?;
 
-        return Ok(goods);
-    });
-    let goods = handle
-        .join()
-        .map_err(|e| errors::ShopCustomError::getCustomError(e))??;
+    Ok(Json(AirdropResponse {
+        signature: signature.to_string(),
+        lamports,
+        balance_lamports,
+    }))
+}
 
+pub fn health_scope() -> Scope {
+    web::scope("/health").service(live).service(ready)
+}
 
-    Ok(Json(goods))
+#[get("/live")]
+pub async fn live() -> Json<HealthReport> {
+    Json(HealthReport::new(Vec::new()))
 }
 
+#[get("/ready")]
+pub async fn ready(shop_state: web::Data<ShopState<'static>>) -> HttpResponse {
+    let checks = shop_state
+        .program_client
+        .check_readiness(
+            shop_state.goods_account_key_pair.pubkey(),
+            shop_state.shop_configurations.health_min_balance_lamports,
+        )
+        .await;
+    let health_report = HealthReport::new(checks);
+
+    if health_report.is_healthy() {
+        HttpResponse::Ok().json(health_report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(health_report)
+    }
+}
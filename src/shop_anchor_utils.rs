@@ -1,38 +1,1002 @@
-
 use super::*;
-pub fn try_get_program(shop_configurations: &ShopConfigurations) -> ShopResult<Program> {
-    let program_id = try_get_program_id(&shop_configurations.program_id)?;
+use actix_web::http::header::EntityTag;
+use anchor_client::anchor_lang::{
+    AccountDeserialize, AccountSerialize, AnchorSerialize, InstructionData, ToAccountMetas,
+};
+use anchor_client::solana_client::nonblocking::rpc_client::RpcClient;
+use anchor_client::solana_client::rpc_config::{RpcSendTransactionConfig, RpcTransactionConfig};
+use anchor_client::solana_sdk::hash::{hashv, Hash};
+use anchor_client::solana_sdk::instruction::Instruction;
+use anchor_client::solana_sdk::message::Message;
+use anchor_client::solana_sdk::packet::PACKET_DATA_SIZE;
+use anchor_client::solana_sdk::signature::Signature;
+use anchor_client::solana_sdk::signer::Signer;
+use anchor_client::solana_sdk::transaction::Transaction;
+use anchor_client::solana_sdk::{bpf_loader, bpf_loader_deprecated, bpf_loader_upgradeable};
+use log::warn;
+use serde::Serialize;
+use serde_json::json;
+use shop_manager::{Good, GoodsAccount};
+use std::time::Duration;
 
-    let cluster = get_cluster(shop_configurations);
-    let payer_key_pair = keypair_from_bytes(&shop_configurations.payer_key_pair_bytes)?;
-    let client = configure_and_get_client(cluster, payer_key_pair);
+pub fn get_cluster(shop_configurations: &ShopConfigurations) -> Cluster {
+    try_parse_cluster(
+        &shop_configurations.cluster,
+        &shop_configurations.cluster_url,
+        &shop_configurations.cluster_ws_url,
+    )
+    .expect("the cluster is validated when the configurations are loaded")
+}
+
+/// Resolves the configured cluster, `custom` needs a http(s) `cluster_url` and
+/// the websocket url is derived from it when `cluster_ws_url` is empty.
+pub fn try_parse_cluster(
+    cluster: &str,
+    cluster_url: &str,
+    cluster_ws_url: &str,
+) -> Result<Cluster, String> {
+    match cluster.trim().to_lowercase().as_str() {
+        "localnet" => Ok(Cluster::Localnet),
+        "devnet" => Ok(Cluster::Devnet),
+        "testnet" => Ok(Cluster::Testnet),
+        "mainnet-beta" | "mainnet" => Ok(Cluster::Mainnet),
+        "custom" => {
+            let cluster_url = cluster_url.trim();
+            if cluster_url.is_empty() {
+                return Err("CLUSTER_URL is required when CLUSTER is custom".to_string());
+            }
+            let derived_ws_url = derive_ws_url(cluster_url)
+                .map_err(|e| format!("CLUSTER_URL `{cluster_url}` is invalid: {e}"))?;
 
-    let program = client.program(program_id);
-    return Ok(program);
+            let cluster_ws_url = match cluster_ws_url.trim() {
+                "" => derived_ws_url,
+                cluster_ws_url
+                    if cluster_ws_url.starts_with("ws://")
+                        || cluster_ws_url.starts_with("wss://") =>
+                {
+                    cluster_ws_url.to_string()
+                }
+                cluster_ws_url => {
+                    return Err(format!(
+                        "CLUSTER_WS_URL `{cluster_ws_url}` must start with ws:// or wss://"
+                    ))
+                }
+            };
+            Ok(Cluster::Custom(cluster_url.to_string(), cluster_ws_url))
+        }
+        _ => Err(format!(
+            "CLUSTER `{cluster}` is not supported, expected one of localnet, devnet, testnet, mainnet-beta or custom"
+        )),
+    }
 }
 
-pub fn configure_and_get_client(cluster: Cluster, payer_key_pair: Keypair) -> Client {
-    let client = Client::new_with_options(
-        cluster,
-        Rc::new(payer_key_pair),
-        CommitmentConfig::processed(),
-    );
-    client
+/// Websocket url of a rpc url, solana validators serve it on the rpc port + 1.
+fn derive_ws_url(cluster_url: &str) -> Result<String, String> {
+    let (scheme, rest) = cluster_url
+        .split_once("://")
+        .ok_or_else(|| "expected an http:// or https:// url".to_string())?;
+    let ws_scheme = match scheme {
+        "http" => "ws",
+        "https" => "wss",
+        _ => {
+            return Err(format!(
+                "unsupported scheme `{scheme}`, expected http or https"
+            ))
+        }
+    };
+
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, ""),
+    };
+    // ipv6 hosts are bracketed, e.g. [::1]:8899
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, Some(port)),
+        _ => (authority, None),
+    };
+    if host.is_empty() {
+        return Err("missing host".to_string());
+    }
+
+    match port {
+        Some(port) => {
+            let port = port
+                .parse::<u16>()
+                .map_err(|_| format!("`{port}` is not a valid port"))?;
+            let ws_port = port
+                .checked_add(1)
+                .ok_or_else(|| format!("no websocket port after {port}"))?;
+            Ok(format!("{ws_scheme}://{host}:{ws_port}{path}"))
+        }
+        None => Ok(format!("{ws_scheme}://{host}{path}")),
+    }
 }
 
-pub fn get_cluster(shop_configurations: &ShopConfigurations) -> Cluster {
-    let cluster: &str = &shop_configurations.cluster;
+pub fn find_good_by_id(goods: &[Good], good_id: u64) -> Option<Good> {
+    goods.iter().find(|good| good.id as u64 == good_id).cloned()
+}
 
-    match cluster {
-        "localnet" => Cluster::Localnet,
-        "devnet" => Cluster::Devnet,
-        "custom" => {
-            let url = Cluster::Custom(
-                shop_configurations.cluster_url.to_string(),
-                shop_configurations.cluster_ws_url.to_string(),
+/// Entity tag of a good, it changes whenever one of its fields does.
+pub fn get_good_etag(good: &Good) -> EntityTag {
+    get_goods_etag(std::slice::from_ref(good))
+}
+
+/// Entity tag of the goods held by the goods account, it changes whenever a
+/// good is inserted, updated or deleted. The slot the account is read at is
+/// left out: it moves on every read even when the goods did not change.
+pub fn get_goods_etag(goods: &[Good]) -> EntityTag {
+    let serialized_goods =
+        serde_json::to_vec(goods).expect("goods are always serializable to json");
+    EntityTag::new_strong(hashv(&[&serialized_goods]).to_string())
+}
+
+/// Checks a good before it is sent to the program so invalid goods are
+/// rejected without paying for a failed transaction. Every invalid field is
+/// reported at once.
+pub fn validate_good(good: &Good) -> Result<(), errors::ShopApiError> {
+    let mut field_errors = Vec::new();
+    let mut invalid = |field: &'static str, message: String| {
+        field_errors.push(errors::FieldError { field, message })
+    };
+
+    if good.id as u64 == 0 {
+        invalid("id", "must be greater than 0".to_string());
+    }
+
+    if good.name.trim().is_empty() {
+        invalid("name", "must not be empty".to_string());
+    }
+
+    if !is_image_url(&good.image) {
+        invalid("image", "must be an http:// or https:// url".to_string());
+    }
+
+    if good.price as u64 == 0 {
+        invalid("price", "must be greater than 0".to_string());
+    }
+
+    if field_errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors::ShopApiError::InvalidGood(field_errors))
+    }
+}
+
+/// The goods of a goods account and the bytes the account has for them, so a
+/// good can be checked against the space the program allocated before paying
+/// for a transaction that would fail with `GoodsAccountFull`.
+pub struct GoodsAccountSpace {
+    goods: Vec<Good>,
+    capacity: usize,
+}
+
+impl GoodsAccountSpace {
+    /// `capacity` is the room for the serialized goods, the account size
+    /// without the discriminator and the other fields of the account.
+    pub fn new(goods: Vec<Good>, capacity: usize) -> Self {
+        GoodsAccountSpace { goods, capacity }
+    }
+
+    fn from_account(
+        goods_account: GoodsAccount,
+        account_size: usize,
+    ) -> Result<Self, errors::ShopApiError> {
+        let mut account_data = Vec::new();
+        goods_account
+            .try_serialize(&mut account_data)
+            .map_err(|e| errors::ShopApiError::Internal(format!("invalid goods account: {e}")))?;
+        let goods_size = serialized_size(&goods_account.goods)?;
+        let capacity = account_size.saturating_sub(account_data.len() - goods_size);
+        Ok(GoodsAccountSpace::new(goods_account.goods, capacity))
+    }
+
+    pub fn goods(&self) -> &[Good] {
+        &self.goods
+    }
+
+    pub fn into_goods(self) -> Vec<Good> {
+        self.goods
+    }
+
+    /// Puts `good` in place of the good with the same id, or after the other
+    /// goods, when the account still has room for it once serialized.
+    pub fn try_put(&mut self, good: &Good) -> Result<(), errors::ShopApiError> {
+        let single_good_size = serialized_size(std::slice::from_ref(good))?;
+        if single_good_size > self.capacity {
+            let message = format!(
+                "name and image are {} bytes too long for the goods account",
+                single_good_size - self.capacity
             );
-            url
+            return Err(errors::ShopApiError::InvalidGood(vec![
+                errors::FieldError {
+                    field: "name",
+                    message: message.clone(),
+                },
+                errors::FieldError {
+                    field: "image",
+                    message,
+                },
+            ]));
+        }
+
+        let position = self
+            .goods
+            .iter()
+            .position(|existing_good| existing_good.id == good.id);
+        let previous_good = match position {
+            Some(position) => Some(std::mem::replace(&mut self.goods[position], good.clone())),
+            None => {
+                self.goods.push(good.clone());
+                None
+            }
+        };
+
+        let goods_size = serialized_size(&self.goods)?;
+        if goods_size <= self.capacity {
+            return Ok(());
+        }
+
+        match (position, previous_good) {
+            (Some(position), Some(previous_good)) => self.goods[position] = previous_good,
+            _ => {
+                self.goods.pop();
+            }
+        }
+        Err(errors::ShopApiError::Program(
+            errors::ShopProgramError::goods_account_full(format!(
+                "the goods account needs {} more bytes for good {}",
+                goods_size - self.capacity,
+                good.id
+            )),
+        ))
+    }
+}
+
+fn serialized_size(goods: &[Good]) -> Result<usize, errors::ShopApiError> {
+    goods
+        .try_to_vec()
+        .map(|data| data.len())
+        .map_err(|e| errors::ShopApiError::Internal(format!("invalid goods: {e}")))
+}
+
+fn is_image_url(image: &str) -> bool {
+    let rest = match image
+        .strip_prefix("https://")
+        .or_else(|| image.strip_prefix("http://"))
+    {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    !host.is_empty() && !image.chars().any(char::is_whitespace)
+}
+
+/// Page size of `GET /goods` when the request does not set `limit`.
+pub const DEFAULT_GOODS_PAGE_SIZE: usize = 50;
+pub const MAX_GOODS_PAGE_SIZE: usize = 500;
+
+/// Filters, sorts and pages the goods of the goods account.
+pub fn query_goods(
+    goods: Vec<Good>,
+    query: &GoodsQuery,
+) -> Result<GoodsPage, errors::ShopApiError> {
+    let invalid = |reason: String, details: serde_json::Value| errors::ShopApiError::Validation {
+        reason,
+        details,
+    };
+
+    if let (Some(min_price), Some(max_price)) = (query.min_price, query.max_price) {
+        if min_price > max_price {
+            return Err(invalid(
+                "min_price can not be greater than max_price".to_string(),
+                json!({ "min_price": min_price, "max_price": max_price }),
+            ));
+        }
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_GOODS_PAGE_SIZE);
+    if limit == 0 || limit > MAX_GOODS_PAGE_SIZE {
+        return Err(invalid(
+            format!("limit must be between 1 and {MAX_GOODS_PAGE_SIZE}"),
+            json!({ "limit": limit }),
+        ));
+    }
+    let descending = match query.order.as_deref() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(order) => {
+            return Err(invalid(
+                format!("order `{order}` must be asc or desc"),
+                json!({ "order": order }),
+            ))
+        }
+    };
+
+    let name = query.name.as_ref().map(|name| name.to_lowercase());
+    let mut goods: Vec<Good> = goods
+        .into_iter()
+        .filter(|good| match &name {
+            Some(name) => good.name.to_lowercase().contains(name.as_str()),
+            None => true,
+        })
+        .filter(|good| {
+            query
+                .min_price
+                .map_or(true, |min_price| good.price as u64 >= min_price)
+        })
+        .filter(|good| {
+            query
+                .max_price
+                .map_or(true, |max_price| good.price as u64 <= max_price)
+        })
+        .collect();
+
+    match query.sort.as_deref() {
+        None | Some("id") => goods.sort_by_key(|good| good.id as u64),
+        Some("name") => goods.sort_by(|a, b| {
+            a.name
+                .to_lowercase()
+                .cmp(&b.name.to_lowercase())
+                .then((a.id as u64).cmp(&(b.id as u64)))
+        }),
+        Some("price") => goods.sort_by_key(|good| (good.price as u64, good.id as u64)),
+        Some(sort) => {
+            return Err(invalid(
+                format!("sort `{sort}` must be one of id, name or price"),
+                json!({ "sort": sort }),
+            ))
+        }
+    }
+    if descending {
+        goods.reverse();
+    }
+
+    let total = goods.len();
+    let offset = query.offset.unwrap_or(0);
+    let goods: Vec<Good> = goods.into_iter().skip(offset).take(limit).collect();
+    let next_offset = Some(offset + goods.len()).filter(|next_offset| *next_offset < total);
+
+    Ok(GoodsPage {
+        goods,
+        total,
+        limit,
+        offset,
+        next_offset,
+    })
+}
+
+/// A transaction that did not reach its commitment. `signature` is set once it
+/// was signed, unless the failure `is_on_chain` it may still have landed.
+#[derive(Debug)]
+pub struct TransactionFailure {
+    pub signature: Option<Signature>,
+    pub error: errors::ShopApiError,
+}
+
+impl TransactionFailure {
+    /// The program or the runtime refused the transaction, nothing it holds
+    /// was applied and its instructions can be sent again.
+    pub fn is_on_chain(&self) -> bool {
+        matches!(
+            self.error,
+            errors::ShopApiError::Program(_) | errors::ShopApiError::InsufficientFunds(_)
+        )
+    }
+}
+
+/// Async counterpart of `Program` used by the request handlers, it talks to the
+/// cluster through the nonblocking rpc client so handlers never block a worker.
+pub struct ShopProgramClient {
+    pub rpc: RpcClient,
+    pub payer: Keypair,
+    pub program_id: Pubkey,
+    pub cluster: Cluster,
+}
+
+impl ShopProgramClient {
+    pub fn new(
+        shop_configurations: &ShopConfigurations,
+    ) -> Result<ShopProgramClient, errors::ShopApiError> {
+        // PROGRAM_ID is validated with the other configurations at startup
+        let program_id = try_get_program_id(&shop_configurations.program_id)
+            .map_err(|e| errors::ShopApiError::Internal(e.0))?;
+        let cluster = get_cluster(shop_configurations);
+        let payer = keypair_from_bytes(&shop_configurations.payer_key_pair_bytes)?;
+        let rpc = RpcClient::new_with_commitment(
+            cluster.url().to_string(),
+            shop_configurations.commitment,
+        );
+
+        Ok(ShopProgramClient {
+            rpc,
+            payer,
+            program_id,
+            cluster,
+        })
+    }
+
+    pub fn instruction(
+        &self,
+        accounts: impl ToAccountMetas,
+        args: impl InstructionData,
+    ) -> Instruction {
+        Instruction {
+            program_id: self.program_id,
+            accounts: accounts.to_account_metas(None),
+            data: args.data(),
+        }
+    }
+
+    pub async fn send_instruction(
+        &self,
+        accounts: impl ToAccountMetas,
+        args: impl InstructionData,
+        extra_signers: &[&Keypair],
+        commitment: CommitmentConfig,
+    ) -> Result<TransactionReceipt, errors::ShopApiError> {
+        let instruction = self.instruction(accounts, args);
+        self.send_instructions(&[instruction], extra_signers, commitment)
+            .await
+    }
+
+    /// Sends the instructions in a single transaction paid for by the payer.
+    pub async fn send_instructions(
+        &self,
+        instructions: &[Instruction],
+        extra_signers: &[&Keypair],
+        commitment: CommitmentConfig,
+    ) -> Result<TransactionReceipt, errors::ShopApiError> {
+        self.try_send_instructions(instructions, extra_signers, commitment)
+            .await
+            .map_err(|failure| failure.error)
+    }
+
+    /// Same as `send_instructions`, a failure also carries the signature of the
+    /// transaction once it was signed.
+    pub async fn try_send_instructions(
+        &self,
+        instructions: &[Instruction],
+        extra_signers: &[&Keypair],
+        commitment: CommitmentConfig,
+    ) -> Result<TransactionReceipt, TransactionFailure> {
+        let unsigned = |error: errors::ShopApiError| TransactionFailure {
+            signature: None,
+            error,
+        };
+        let mut signers = vec![&self.payer];
+        signers.extend_from_slice(extra_signers);
+
+        let recent_blockhash = self
+            .rpc
+            .get_latest_blockhash()
+            .await
+            .map_err(|e| unsigned(e.into()))?;
+        let mut transaction = Transaction::new_with_payer(instructions, Some(&self.payer.pubkey()));
+        transaction
+            .try_sign(&signers[..], recent_blockhash)
+            .map_err(|e| unsigned(errors::ShopApiError::Signing(e.to_string())))?;
+        let fee_lamports = self
+            .rpc
+            .get_fee_for_message(&transaction.message)
+            .await
+            .map_err(|e| unsigned(e.into()))?;
+
+        let signature = transaction.signatures[0];
+        let signed = |error: errors::ShopApiError| TransactionFailure {
+            signature: Some(signature),
+            error,
+        };
+        self.rpc
+            .send_transaction_with_config(
+                &transaction,
+                RpcSendTransactionConfig {
+                    preflight_commitment: Some(commitment.commitment),
+                    ..RpcSendTransactionConfig::default()
+                },
+            )
+            .await
+            .map_err(|e| signed(e.into()))?;
+        idempotency::record_submitted_transaction(&signature);
+        match api_keys::current_api_key_label() {
+            Some(label) => info!("transaction {signature} sent for api key {label}"),
+            None => info!("transaction {signature} sent"),
+        }
+        self.wait_for_commitment(&signature, &recent_blockhash, commitment)
+            .await
+            .map_err(signed)?;
+        self.get_transaction_receipt(signature, fee_lamports)
+            .await
+            .map_err(signed)
+    }
+
+    /// Splits the instructions in groups that each fit in one transaction signed
+    /// by the payer alone, keeping their order.
+    pub fn pack_instructions<T>(
+        &self,
+        instructions: Vec<(T, Instruction)>,
+    ) -> Vec<Vec<(T, Instruction)>> {
+        let mut groups: Vec<Vec<(T, Instruction)>> = Vec::new();
+        let mut group_instructions: Vec<Instruction> = Vec::new();
+
+        for (item, instruction) in instructions {
+            group_instructions.push(instruction.clone());
+            let fits = self.transaction_size(&group_instructions) <= PACKET_DATA_SIZE;
+            match groups.last_mut() {
+                Some(group) if fits => group.push((item, instruction)),
+                _ => {
+                    group_instructions = vec![instruction.clone()];
+                    groups.push(vec![(item, instruction)]);
+                }
+            }
+        }
+        groups
+    }
+
+    fn transaction_size(&self, instructions: &[Instruction]) -> usize {
+        let message = Message::new(instructions, Some(&self.payer.pubkey()));
+        let signatures = message.header.num_required_signatures as usize;
+        // the signature count is a compact u16, a single byte below 128 signatures
+        1 + signatures * 64 + message.serialize().len()
+    }
+
+    /// Waits until the transaction reaches `commitment`, giving up once its
+    /// blockhash expired without the transaction having landed.
+    async fn wait_for_commitment(
+        &self,
+        signature: &Signature,
+        recent_blockhash: &Hash,
+        commitment: CommitmentConfig,
+    ) -> Result<(), errors::ShopApiError> {
+        loop {
+            match self
+                .rpc
+                .get_signature_status_with_commitment(signature, commitment)
+                .await?
+            {
+                Some(Ok(())) => return Ok(()),
+                Some(Err(transaction_error)) => {
+                    let logs = self.fetch_transaction_logs(signature).await;
+                    return Err(errors::decode_transaction_error(&transaction_error, logs));
+                }
+                None => {
+                    let landed = self
+                        .rpc
+                        .get_signature_status_with_commitment(
+                            signature,
+                            CommitmentConfig::processed(),
+                        )
+                        .await?
+                        .is_some();
+                    if !landed
+                        && !self
+                            .rpc
+                            .is_blockhash_valid(recent_blockhash, CommitmentConfig::processed())
+                            .await?
+                    {
+                        return Err(errors::ShopApiError::Rpc(format!(
+                            "transaction {signature} expired before reaching {} commitment",
+                            shop_solana_utils::get_commitment_level_name(commitment)
+                        )));
+                    }
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                }
+            }
+        }
+    }
+
+    /// Logs of a transaction that failed on chain, none when the node does not
+    /// serve it yet, the error is then decoded from its number alone.
+    async fn fetch_transaction_logs(&self, signature: &Signature) -> Vec<String> {
+        match self
+            .rpc
+            .get_transaction_with_config(
+                signature,
+                RpcTransactionConfig {
+                    commitment: Some(CommitmentConfig::confirmed()),
+                    max_supported_transaction_version: Some(0),
+                    ..RpcTransactionConfig::default()
+                },
+            )
+            .await
+        {
+            Ok(transaction) => transaction
+                .transaction
+                .meta
+                .and_then(|meta| meta.log_messages)
+                .unwrap_or_default(),
+            Err(e) => {
+                debug!("logs of transaction {signature} are not available: {e}");
+                Vec::new()
+            }
         }
-        _ => Cluster::default(),
     }
-}
\ No newline at end of file
+
+    async fn get_transaction_receipt(
+        &self,
+        signature: Signature,
+        fee_lamports: u64,
+    ) -> Result<TransactionReceipt, errors::ShopApiError> {
+        let status = self
+            .rpc
+            .get_signature_statuses(&[signature])
+            .await?
+            .value
+            .into_iter()
+            .next()
+            .flatten();
+        let (slot, commitment) = match status {
+            Some(status) => (
+                Some(status.slot),
+                status.confirmation_status.and_then(get_commitment_name),
+            ),
+            None => (None, None),
+        };
+
+        Ok(TransactionReceipt {
+            signature: signature.to_string(),
+            slot,
+            commitment,
+            fee_lamports,
+            explorer_url: shop_solana_utils::get_explorer_url(
+                &signature.to_string(),
+                &self.cluster,
+            ),
+        })
+    }
+
+    /// Looks up a transaction sent to the cluster, the status is searched in the
+    /// whole ledger history while the details are only available once confirmed.
+    pub async fn fetch_transaction_status(
+        &self,
+        signature: Signature,
+    ) -> Result<TransactionStatusInfo, errors::ShopApiError> {
+        let status = self
+            .rpc
+            .get_signature_statuses_with_history(&[signature])
+            .await?
+            .value
+            .into_iter()
+            .next()
+            .flatten();
+        let transaction = match self
+            .rpc
+            .get_transaction_with_config(
+                &signature,
+                RpcTransactionConfig {
+                    commitment: Some(CommitmentConfig::confirmed()),
+                    max_supported_transaction_version: Some(0),
+                    ..RpcTransactionConfig::default()
+                },
+            )
+            .await
+        {
+            Ok(transaction) => Some(transaction),
+            Err(e) => {
+                debug!("transaction {signature} is not available yet: {e}");
+                None
+            }
+        };
+
+        if status.is_none() && transaction.is_none() {
+            return Err(errors::ShopApiError::TransactionNotFound(
+                signature.to_string(),
+            ));
+        }
+
+        let meta = transaction
+            .as_ref()
+            .and_then(|transaction| transaction.transaction.meta.as_ref());
+        let logs = meta
+            .and_then(|meta| meta.log_messages.clone())
+            .unwrap_or_default();
+        let transaction_error = status
+            .as_ref()
+            .and_then(|status| status.err.clone())
+            .or_else(|| meta.and_then(|meta| meta.err.clone()));
+        let error = transaction_error.map(|transaction_error| {
+            errors::decode_transaction_error(&transaction_error, logs.clone()).to_response_body()
+        });
+
+        Ok(TransactionStatusInfo {
+            signature: signature.to_string(),
+            slot: status
+                .as_ref()
+                .map(|status| status.slot)
+                .or_else(|| transaction.as_ref().map(|transaction| transaction.slot)),
+            confirmations: status.as_ref().and_then(|status| status.confirmations),
+            commitment: status
+                .and_then(|status| status.confirmation_status)
+                .and_then(get_commitment_name),
+            succeeded: error.is_none(),
+            error,
+            block_time: transaction
+                .as_ref()
+                .and_then(|transaction| transaction.block_time),
+            fee_lamports: meta.map(|meta| meta.fee),
+            logs,
+            explorer_url: shop_solana_utils::get_explorer_url(
+                &signature.to_string(),
+                &self.cluster,
+            ),
+        })
+    }
+
+    /// Runs every readiness check concurrently, a failing check never stops the others.
+    pub async fn check_readiness(
+        &self,
+        goods_account_pubkey: Pubkey,
+        min_balance_lamports: u64,
+    ) -> Vec<HealthCheck> {
+        let (rpc, program, goods_account, payer_balance) = tokio::join!(
+            self.check_rpc(),
+            self.check_program(),
+            self.check_goods_account(goods_account_pubkey),
+            self.check_payer_balance(min_balance_lamports),
+        );
+        vec![rpc, program, goods_account, payer_balance]
+    }
+
+    /// Problems that would make every request fail, checked before the server
+    /// listens. A goods account that does not exist yet is not one of them
+    /// since `POST /initialize` creates it.
+    pub async fn verify_deployment(&self, goods_account_pubkey: Pubkey) -> Vec<String> {
+        let rpc = self.check_rpc().await;
+        if !rpc.healthy {
+            return vec![format!("{}: {}", rpc.name, rpc.message)];
+        }
+
+        let (program, goods_account) = tokio::join!(
+            self.check_program(),
+            self.fetch_goods_account_status(goods_account_pubkey, self.rpc.commitment()),
+        );
+        let mut problems = Vec::new();
+        if !program.healthy {
+            problems.push(format!("{}: {}", program.name, program.message));
+        }
+        match goods_account {
+            Ok((None, _)) => warn!(
+                "goods account {goods_account_pubkey} does not exist yet, POST /initialize creates it"
+            ),
+            Ok((Some(owner), _)) if owner != self.program_id => problems.push(format!(
+                "goods_account: {goods_account_pubkey} is owned by {owner}, not by the program"
+            )),
+            Ok((Some(_), false)) => problems.push(format!(
+                "goods_account: {goods_account_pubkey} does not deserialize as a GoodsAccount"
+            )),
+            Ok((Some(_), true)) => {}
+            Err(e) => problems.push(format!("goods_account: {e}")),
+        }
+        problems
+    }
+
+    async fn check_rpc(&self) -> HealthCheck {
+        let details = json!({ "url": self.rpc.url() });
+        if let Err(e) = self.rpc.get_health().await {
+            return HealthCheck::failed("rpc", format!("node is unhealthy: {e}"), details);
+        }
+        match self.rpc.get_version().await {
+            Ok(version) => HealthCheck::passed(
+                "rpc",
+                format!("solana-core {}", version.solana_core),
+                json!({ "url": self.rpc.url(), "solana_core": version.solana_core }),
+            ),
+            Err(e) => HealthCheck::failed("rpc", format!("get_version failed: {e}"), details),
+        }
+    }
+
+    async fn check_program(&self) -> HealthCheck {
+        let details = json!({ "program_id": self.program_id.to_string() });
+        match self
+            .rpc
+            .get_account_with_commitment(&self.program_id, self.rpc.commitment())
+            .await
+        {
+            Ok(response) => match response.value {
+                Some(account) if account.executable && !is_bpf_loader(&account.owner) => {
+                    HealthCheck::failed(
+                        "program",
+                        format!(
+                            "PROGRAM_ID is owned by {}, not by a BPF loader",
+                            account.owner
+                        ),
+                        json!({
+                            "program_id": self.program_id.to_string(),
+                            "owner": account.owner.to_string(),
+                        }),
+                    )
+                }
+                Some(account) if account.executable => HealthCheck::passed(
+                    "program",
+                    "program is deployed".to_string(),
+                    json!({
+                        "program_id": self.program_id.to_string(),
+                        "owner": account.owner.to_string(),
+                    }),
+                ),
+                Some(_) => HealthCheck::failed(
+                    "program",
+                    "PROGRAM_ID is not an executable account".to_string(),
+                    details,
+                ),
+                None => HealthCheck::failed(
+                    "program",
+                    "PROGRAM_ID does not exist on the cluster".to_string(),
+                    details,
+                ),
+            },
+            Err(e) => HealthCheck::failed("program", e.to_string(), details),
+        }
+    }
+
+    async fn check_goods_account(&self, goods_account_pubkey: Pubkey) -> HealthCheck {
+        match self
+            .fetch_goods_account_status(goods_account_pubkey, self.rpc.commitment())
+            .await
+        {
+            Ok((owner, initialized)) => {
+                let details = json!({
+                    "address": goods_account_pubkey.to_string(),
+                    "owner": owner.map(|owner| owner.to_string()),
+                    "initialized": initialized,
+                });
+                match owner {
+                    Some(owner) if owner == self.program_id && initialized => HealthCheck::passed(
+                        "goods_account",
+                        "goods account is owned by the program".to_string(),
+                        details,
+                    ),
+                    Some(owner) if owner == self.program_id => HealthCheck::failed(
+                        "goods_account",
+                        "goods account does not deserialize as a GoodsAccount".to_string(),
+                        details,
+                    ),
+                    Some(owner) => HealthCheck::failed(
+                        "goods_account",
+                        format!("goods account is owned by {owner}, not by the program"),
+                        details,
+                    ),
+                    None => HealthCheck::failed(
+                        "goods_account",
+                        "goods account does not exist, POST /initialize creates it".to_string(),
+                        details,
+                    ),
+                }
+            }
+            Err(e) => HealthCheck::failed(
+                "goods_account",
+                e.to_string(),
+                json!({ "address": goods_account_pubkey.to_string() }),
+            ),
+        }
+    }
+
+    async fn check_payer_balance(&self, min_balance_lamports: u64) -> HealthCheck {
+        let payer = self.payer.pubkey();
+        match self.rpc.get_balance(&payer).await {
+            Ok(lamports) => {
+                let details = json!({
+                    "payer": payer.to_string(),
+                    "lamports": lamports,
+                    "min_balance_lamports": min_balance_lamports,
+                });
+                if lamports >= min_balance_lamports {
+                    HealthCheck::passed("payer_balance", format!("{lamports} lamports"), details)
+                } else {
+                    HealthCheck::failed(
+                        "payer_balance",
+                        format!("{lamports} lamports is below {min_balance_lamports}"),
+                        details,
+                    )
+                }
+            }
+            Err(e) => HealthCheck::failed(
+                "payer_balance",
+                e.to_string(),
+                json!({ "payer": payer.to_string() }),
+            ),
+        }
+    }
+
+    pub async fn fetch_goods_account(
+        &self,
+        goods_account_pubkey: Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<GoodsAccount, errors::ShopApiError> {
+        Ok(self
+            .fetch_goods_account_with_size(goods_account_pubkey, commitment)
+            .await?
+            .0)
+    }
+
+    /// Returns the goods of the goods account with the room the account has
+    /// for them, see `GoodsAccountSpace::try_put`.
+    pub async fn fetch_goods_account_space(
+        &self,
+        goods_account_pubkey: Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<GoodsAccountSpace, errors::ShopApiError> {
+        let (goods_account, account_size) = self
+            .fetch_goods_account_with_size(goods_account_pubkey, commitment)
+            .await?;
+        GoodsAccountSpace::from_account(goods_account, account_size)
+    }
+
+    async fn fetch_goods_account_with_size(
+        &self,
+        goods_account_pubkey: Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<(GoodsAccount, usize), errors::ShopApiError> {
+        let account = self
+            .rpc
+            .get_account_with_commitment(&goods_account_pubkey, commitment)
+            .await?
+            .value
+            .ok_or_else(|| {
+                errors::ShopApiError::AccountNotFound(goods_account_pubkey.to_string())
+            })?;
+
+        let goods_account = GoodsAccount::try_deserialize(&mut account.data.as_slice())
+            .map_err(|e| errors::ShopApiError::Internal(format!("invalid goods account: {e}")))?;
+        Ok((goods_account, account.data.len()))
+    }
+
+    /// Returns the owner of the goods account and whether it holds an
+    /// initialized `GoodsAccount` of this program.
+    pub async fn fetch_goods_account_status(
+        &self,
+        goods_account_pubkey: Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<(Option<Pubkey>, bool), errors::ShopApiError> {
+        let account = self
+            .rpc
+            .get_account_with_commitment(&goods_account_pubkey, commitment)
+            .await?
+            .value;
+
+        Ok(match account {
+            Some(account) => {
+                let initialized = account.owner == self.program_id
+                    && GoodsAccount::try_deserialize(&mut account.data.as_slice()).is_ok();
+                (Some(account.owner), initialized)
+            }
+            None => (None, false),
+        })
+    }
+
+    /// Signature of the oldest transaction of the goods account, the one that
+    /// initialized it, when the rpc node still has it in its history.
+    pub async fn fetch_initialize_signature(
+        &self,
+        goods_account_pubkey: Pubkey,
+    ) -> Result<Option<String>, errors::ShopApiError> {
+        let signatures = self
+            .rpc
+            .get_signatures_for_address(&goods_account_pubkey)
+            .await?;
+        Ok(signatures.last().map(|status| status.signature.clone()))
+    }
+
+    pub async fn fetch_goods(
+        &self,
+        goods_account_pubkey: Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<Vec<Good>, errors::ShopApiError> {
+        let goods_account = self
+            .fetch_goods_account(goods_account_pubkey, commitment)
+            .await?;
+        Ok(goods_account.goods)
+    }
+}
+
+pub fn is_bpf_loader(owner: &Pubkey) -> bool {
+    *owner == bpf_loader::id()
+        || *owner == bpf_loader_upgradeable::id()
+        || *owner == bpf_loader_deprecated::id()
+}
+
+/// Name of a confirmation status as the rpc reports it (`processed`, `confirmed`, `finalized`).
+fn get_commitment_name(confirmation_status: impl Serialize) -> Option<String> {
+    serde_json::to_value(confirmation_status)
+        .ok()?
+        .as_str()
+        .map(str::to_string)
+}
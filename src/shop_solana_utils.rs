@@ -1,100 +1,253 @@
 use super::*;
-pub fn keypair_from_bytes(key_pair_bytes: &[u8]) -> ShopResult<Keypair> {
+pub fn keypair_from_bytes(key_pair_bytes: &[u8]) -> Result<Keypair, errors::ShopApiError> {
     let key_pair = Keypair::from_bytes(&key_pair_bytes)
-        .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+        .map_err(|e| errors::ShopApiError::Signing(e.to_string()))?;
     Ok(key_pair)
 }
 
 
-use anchor_client::solana_sdk::native_token::LAMPORTS_PER_SOL;
-use log::error;
-pub fn get_key_pair_bytes_from_env_string_return_random_if_no_key_string_found(
-    optional_string_key_pair: Option<&str>,
-) -> [u8; 64] {
-    info!("fetching keypair info...");
-    match optional_string_key_pair {
-        Some(key_pair_string) => {
-            info!("success getting keypair from env");
-            match try_parse_key_pair(key_pair_string) {
-                Ok(key_pair) => key_pair.to_bytes(),
-                Err(e) => {
-                    error!("{e:#?}");
-                    get_random_key_pair().to_bytes()
-                }
-            }
+use anchor_client::solana_sdk::native_token::lamports_to_sol;
+use anchor_client::solana_sdk::signature::Signature;
+use anchor_client::solana_sdk::signer::Signer;
+use log::warn;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+/// Loads the keypair configured under `key`, a missing or malformed keypair is
+/// an error unless `allow_ephemeral_keys` is set, in which case a random keypair is used.
+pub fn get_key_pair_bytes(
+    key: &str,
+    optional_key_pair: Option<&str>,
+    allow_ephemeral_keys: bool,
+) -> ShopResult<[u8; 64]> {
+    info!("fetching {key} keypair info...");
+    let key_pair = match optional_key_pair {
+        Some(key_pair_string) => try_parse_key_pair(key_pair_string)
+            .map_err(|e| errors::ShopCustomError(format!("{key} is not a valid keypair: {e}")))?,
+        None if allow_ephemeral_keys => {
+            let key_pair = get_random_key_pair();
+            warn!(
+                "{key} was not set, using the ephemeral keypair {} since ALLOW_EPHEMERAL_KEYS is enabled",
+                key_pair.pubkey()
+            );
+            key_pair
         }
         None => {
-            info!(" key pair was not passed,falling back to a random keypair");
-
-            get_random_key_pair().to_bytes()
+            return Err(Box::new(errors::ShopCustomError(format!(
+                "{key} is not set, set it or enable ALLOW_EPHEMERAL_KEYS to use a random keypair"
+            ))))
         }
-    }
+    };
+    info!("{key} keypair loaded, pubkey: {}", key_pair.pubkey());
+    Ok(key_pair.to_bytes())
 }
 
 pub fn get_random_key_pair() -> Keypair {
     return Keypair::new();
 }
 
-pub fn try_get_program_id(program_id_as_base_58: &str) -> ShopResult<Pubkey> {
-    let program_id_vec = program_id_as_base_58
-        .from_base58()
-        .map_err(|e| errors::ShopCustomError(format!("{e:#?}")))?;
-    let program_id = Pubkey::new(&program_id_vec);
-    Ok(program_id)
+pub fn try_get_program_id(program_id_as_base_58: &str) -> Result<Pubkey, errors::ShopCustomError> {
+    Pubkey::from_str(program_id_as_base_58.trim()).map_err(|e| {
+        errors::ShopCustomError(format!(
+            "PROGRAM_ID `{program_id_as_base_58}` is not a valid public key: {e}"
+        ))
+    })
 }
-pub fn request_airdrop_for_current_wallet(
-    shop_configurations: &ShopConfigurations,
-) -> ShopResult<String> {
-    info!("request_airdrop_for_current_wallet");
-    let program = try_get_program(&shop_configurations)
-        .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
-    let payer = program.payer();
-    let tx_id = check_balance_of_fee_payer_and_airdrop(&program)?;
-    Ok(tx_id)
+/// Replaces a leading `~/` with the home directory, like a shell would.
+pub fn expand_home_dir(path: &str) -> String {
+    match (path.strip_prefix("~/"), env::var("HOME")) {
+        (Some(relative_path), Ok(home)) => Path::new(&home)
+            .join(relative_path)
+            .to_string_lossy()
+            .into_owned(),
+        _ => path.to_string(),
+    }
 }
 
-
+/// Parses a keypair given as a path to a Solana CLI JSON keypair file, a JSON
+/// byte array, a base58 encoded secret key or a comma separated byte list.
 pub fn try_parse_key_pair(key_pair_string: &str) -> ShopResult<Keypair> {
-    
-    let key_pair_vec = key_pair_string
-        .split(',')
-        .map(|p| p.parse::<u8>())
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+    let key_pair_string = key_pair_string.trim();
+
+    let key_pair_path = expand_home_dir(key_pair_string);
+    if Path::new(&key_pair_path).is_file() {
+        return read_keypair_file(&key_pair_path).map_err(|e| {
+            errors::ShopCustomError(format!(
+                "failed to read keypair file {key_pair_string}: {e}"
+            ))
+            .into()
+        });
+    }
+    // neither base58 nor bytes contain these, a mistyped path is reported as such
+    if key_pair_string.contains('/') || key_pair_string.ends_with(".json") {
+        return Err(Box::new(errors::ShopCustomError(format!(
+            "keypair file not found: {key_pair_path}"
+        ))));
+    }
 
+    let key_pair_vec = if key_pair_string.starts_with('[') {
+        serde_json::from_str::<Vec<u8>>(key_pair_string)?
+    } else if key_pair_string.contains(',') {
+        key_pair_string
+            .split(',')
+            .map(|p| p.trim().parse::<u8>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| errors::ShopCustomError::getCustomError(e))?
+    } else {
+        key_pair_string
+            .from_base58()
+            .map_err(|e| errors::ShopCustomError(format!("invalid base58 secret key: {e:?}")))?
+    };
+
+    if key_pair_vec.len() != 64 {
+        return Err(Box::new(errors::ShopCustomError(format!(
+            "expected 64 keypair bytes but found {}, is this a public key?",
+            key_pair_vec.len()
+        ))));
+    }
     let key_pair = Keypair::from_bytes(&key_pair_vec)
         .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
 
     Ok(key_pair)
 }
 
-pub fn check_balance_of_fee_payer_and_airdrop(
-    program: &Program,
-) -> Result<String, errors::ShopCustomError> {
+/// Reports the payer balance and, when the funding policy allows it, tops it
+/// up with an airdrop. Airdrops are only attempted on localnet and devnet.
+pub async fn fund_payer_if_needed(
+    program_client: &ShopProgramClient,
+    cluster: &Cluster,
+    funding_policy: &FundingPolicy,
+) -> Result<u64, errors::ShopApiError> {
+    let payer = program_client.payer.pubkey();
+    let balance = program_client.rpc.get_balance(&payer).await?;
+    info!(
+        "payer:{} balance is {} SOL on {}",
+        payer,
+        lamports_to_sol(balance),
+        cluster
+    );
 
-info!("check_balance_of_fee_payer_and_airdrop");
-    const SOLS:u64=50;
-    let payer = program.payer();
-    let tx_id = program
-        .rpc()
-        .request_airdrop(&payer, SOLS * LAMPORTS_PER_SOL)
-        .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
+    if balance >= funding_policy.min_balance_lamports {
+        return Ok(balance);
+    }
+    if !funding_policy.airdrop_enabled {
+        warn!(
+            "payer balance is below {} SOL and AIRDROP_ENABLED is off, fund {} manually",
+            lamports_to_sol(funding_policy.min_balance_lamports),
+            payer
+        );
+        return Ok(balance);
+    }
+    if !is_airdrop_supported(cluster) {
+        warn!("airdrops are only available on localnet and devnet, fund {payer} manually");
+        return Ok(balance);
+    }
+
+    request_airdrop_with_retries(
+        program_client,
+        funding_policy.airdrop_lamports,
+        funding_policy,
+    )
+    .await?;
+
+    let balance = program_client.rpc.get_balance(&payer).await?;
     info!(
-        "payer:{} has successfully received an airdrop of {} SOLS",
-        payer,SOLS
+        "payer:{} balance after airdrop is {} SOL",
+        payer,
+        lamports_to_sol(balance)
     );
+    Ok(balance)
+}
 
-    let confirm_transaction = program
-        .rpc()
-        .confirm_transaction(&tx_id)
-        .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
-    info!("status of airdrop transaction:{}", confirm_transaction);
+/// Link to the transaction on the solana explorer, local and custom clusters
+/// are opened through the explorer's custom rpc url support.
+pub fn get_explorer_url(signature: &str, cluster: &Cluster) -> String {
+    let base_url = format!("https://explorer.solana.com/tx/{signature}");
+    match cluster {
+        Cluster::Mainnet => base_url,
+        Cluster::Devnet => format!("{base_url}?cluster=devnet"),
+        Cluster::Testnet => format!("{base_url}?cluster=testnet"),
+        cluster => format!(
+            "{base_url}?cluster=custom&customUrl={}",
+            encode_query_value(cluster.url())
+        ),
+    }
+}
 
-    let balance = program
-        .rpc()
-        .get_balance(&payer)
-        .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
-    info!("payer id: {} current balance is :{}", payer, balance);
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
 
-    Ok(tx_id.to_string())
-}
\ No newline at end of file
+/// Parses the commitment levels accepted in the configuration and in requests.
+pub fn parse_commitment(commitment: &str) -> Option<CommitmentConfig> {
+    match commitment.trim().to_lowercase().as_str() {
+        "processed" => Some(CommitmentConfig::processed()),
+        "confirmed" => Some(CommitmentConfig::confirmed()),
+        "finalized" => Some(CommitmentConfig::finalized()),
+        _ => None,
+    }
+}
+
+pub fn get_commitment_level_name(commitment: CommitmentConfig) -> &'static str {
+    if commitment.is_finalized() {
+        "finalized"
+    } else if commitment.is_confirmed() {
+        "confirmed"
+    } else {
+        "processed"
+    }
+}
+
+pub fn is_airdrop_supported(cluster: &Cluster) -> bool {
+    matches!(cluster, Cluster::Localnet | Cluster::Devnet)
+}
+
+/// Most SOL a single airdrop may ask for.
+pub const MAX_AIRDROP_SOL: f64 = 100.0;
+/// Bounds on the retries so `POST /wallet/airdrop` answers within seconds.
+pub const MAX_AIRDROP_RETRIES: u32 = 5;
+pub const MAX_AIRDROP_BACKOFF_MS: u64 = 8_000;
+
+/// Requests an airdrop for the payer and waits for it to be confirmed, backing
+/// off exponentially between attempts since faucets rate limit aggressively.
+pub async fn request_airdrop_with_retries(
+    program_client: &ShopProgramClient,
+    lamports: u64,
+    funding_policy: &FundingPolicy,
+) -> Result<Signature, errors::ShopApiError> {
+    let payer = program_client.payer.pubkey();
+    let max_backoff = Duration::from_millis(MAX_AIRDROP_BACKOFF_MS);
+    let mut backoff = Duration::from_millis(funding_policy.airdrop_backoff_ms).min(max_backoff);
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+        match program_client.rpc.request_airdrop(&payer, lamports).await {
+            Ok(signature) => {
+                idempotency::record_submitted_transaction(&signature);
+                program_client.rpc.poll_for_signature(&signature).await?;
+                info!(
+                    "payer:{} has successfully received an airdrop of {} SOL, signature:{}",
+                    payer,
+                    lamports_to_sol(lamports),
+                    signature
+                );
+                return Ok(signature);
+            }
+            Err(e) if attempt <= funding_policy.airdrop_max_retries.min(MAX_AIRDROP_RETRIES) => {
+                warn!("airdrop attempt {attempt} failed, retrying in {backoff:?}: {e}");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(max_backoff);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
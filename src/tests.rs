@@ -8,9 +8,13 @@ use super::*;
 use actix_web::{
     body::MessageBody,
     dev::{AppConfig, Service, ServiceFactory, ServiceResponse},
+    http::{header, StatusCode},
     test, web, App,
 };
+use anchor_client::anchor_lang::AnchorSerialize;
 use anchor_client::solana_client::client_error::reqwest::Request;
+use anchor_client::solana_sdk::native_token::LAMPORTS_PER_SOL;
+use anchor_client::solana_sdk::signer::Signer;
 use base58::ToBase58;
 use serde::__private::from_utf8_lossy;
 use shop_manager::Good;
@@ -28,16 +32,17 @@ async fn test_initialize_post_helper() -> Child {
     let app = test::init_service(
         App::new()
             .app_data(web::Data::new(shop_state))
-            .service(routes::initialize)
-            .service(routes::insert_goods),
+            .configure(routes::configure_routes),
     )
     .await;
 
     let req = test::TestRequest::post().uri("/initialize").to_request();
-    let resp = test::call_service(&app, req).await;
-    assert!(resp.status().is_success());
-    let bytes = resp.into_body().try_into_bytes().unwrap();
-    let tx_id = bytes.to_base58();
+    let envelope: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let tx_id = envelope["transaction"]["signature"].as_str().unwrap();
+    assert!(envelope["transaction"]["explorer_url"]
+        .as_str()
+        .unwrap()
+        .contains(tx_id));
     info!("signature:{}", tx_id);
 
     return solana_test_validator;
@@ -99,14 +104,13 @@ async fn test_insert_goods_post() {
     let app = test::init_service(
         App::new()
             .app_data(web::Data::new(shop_state))
-            .service(routes::initialize)
-            .service(routes::insert_goods),
+            .configure(routes::configure_routes),
     )
     .await;
 
     let good = Good {
         name: "unga".to_string(),
-        image: "image1".to_string(),
+        image: "https://example.com/image1.png".to_string(),
         id: 1,
         price: 26,
     };
@@ -115,14 +119,988 @@ async fn test_insert_goods_post() {
         .uri("/insert_goods")
         .set_json(&good)
         .to_request();
-    let goods_vec: Vec<Good> = test::call_and_read_body_json(&app, req).await;
+    let envelope: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let goods_vec: Vec<Good> = serde_json::from_value(envelope["goods"].clone()).unwrap();
     info!("goods_vec:{:?}", goods_vec);
+    assert_eq!(goods_vec, vec![good.clone()]);
+
+    // listing the goods no longer needs a body
+    let req = test::TestRequest::post().uri("/get_all_goods").to_request();
+    let goods_vec: Vec<Good> = test::call_and_read_body_json(&app, req).await;
     assert_eq!(goods_vec, vec![good]);
+
+    let req = test::TestRequest::get().uri("/goods/2").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "GOOD_NOT_FOUND");
     
     tear_down(&mut solana_test_validator)
 
 } 
 
+#[actix_web::test]
+async fn test_goods_resource_contract() {
+    let mut solana_test_validator = test_initialize_post_helper().await;
+    let shop_state = test_utils::setup_configuration_and_return_state()
+        .await
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(shop_state))
+            .configure(routes::configure_routes),
+    )
+    .await;
+
+    let good = Good {
+        name: "rice".to_string(),
+        image: "https://example.com/image1.png".to_string(),
+        id: 1,
+        price: 150,
+    };
+
+    let req = test::TestRequest::post()
+        .uri("/goods")
+        .set_json(&good)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert_eq!(resp.headers().get(header::LOCATION).unwrap(), "/goods/1");
+
+    let req = test::TestRequest::get().uri("/goods/1").to_request();
+    let fetched_good: Good = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(fetched_good, good);
+
+    let req = test::TestRequest::delete().uri("/goods/1").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert!(resp.headers().contains_key(routes::TRANSACTION_SIGNATURE_HEADER));
+
+    let req = test::TestRequest::delete().uri("/goods/1").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    tear_down(&mut solana_test_validator)
+}
+
+#[actix_web::test]
+async fn test_concurrent_if_match_updates() {
+    let mut solana_test_validator = test_initialize_post_helper().await;
+    let shop_state = test_utils::setup_configuration_and_return_state()
+        .await
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(shop_state))
+            .configure(routes::configure_routes),
+    )
+    .await;
+
+    let good = Good {
+        name: "rice".to_string(),
+        image: "https://example.com/image1.png".to_string(),
+        id: 1,
+        price: 150,
+    };
+    let req = test::TestRequest::post()
+        .uri("/goods")
+        .set_json(&good)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+
+    let req = test::TestRequest::get().uri("/goods/1").to_request();
+    let resp = test::call_service(&app, req).await;
+    let etag = resp.headers().get(header::ETAG).unwrap().clone();
+
+    // both requests read the same good, only the first one sent may apply
+    let put = |price| {
+        test::TestRequest::put()
+            .uri("/goods/1")
+            .insert_header((header::IF_MATCH, etag.clone()))
+            .set_json(&Good {
+                price,
+                ..good.clone()
+            })
+            .to_request()
+    };
+    let (first, second) = tokio::join!(
+        test::call_service(&app, put(175)),
+        test::call_service(&app, put(200))
+    );
+    let mut statuses = vec![first.status(), second.status()];
+    statuses.sort();
+    assert_eq!(statuses, vec![StatusCode::OK, StatusCode::PRECONDITION_FAILED]);
+
+    tear_down(&mut solana_test_validator)
+}
+
+#[actix_web::test]
+async fn test_api_error_json_body() {
+    let resp = errors::ShopApiError::GoodNotFound(9999).error_response();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let bytes = resp.into_body().try_into_bytes().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(body["status"], 404);
+    assert_eq!(body["code"], "GOOD_NOT_FOUND");
+    assert_eq!(body["reason"], "Not found");
+    assert_eq!(body["details"]["id"], 9999);
+
+    let resp = errors::ShopApiError::Rpc("connection refused".to_string()).error_response();
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[test]
+fn test_program_error_decoded_from_anchor_logs() {
+    let logs = vec![
+        "Program 8agPo1zq2ZvXLqsgH5RuhxFJGJrsPTSSopZiYixYJXZy invoke [1]".to_string(),
+        "Program log: Instruction: InsertGoods".to_string(),
+        "Program log: AnchorError thrown in programs/shop-manager/src/lib.rs:32. Error Code: GoodAlreadyExists. Error Number: 6000. Error Message: A good with this id already exists.".to_string(),
+        "Program 8agPo1zq2ZvXLqsgH5RuhxFJGJrsPTSSopZiYixYJXZy consumed 5188 of 200000 compute units".to_string(),
+        "Program 8agPo1zq2ZvXLqsgH5RuhxFJGJrsPTSSopZiYixYJXZy failed: custom program error: 0x1770".to_string(),
+    ];
+    let program_error =
+        errors::ShopProgramError::from_logs(Some(6000), "custom program error".to_string(), logs);
+
+    assert_eq!(program_error.code, Some(6000));
+    assert_eq!(program_error.name.as_deref(), Some("GoodAlreadyExists"));
+    assert_eq!(program_error.message, "A good with this id already exists");
+    assert_eq!(program_error.logs.len(), 3);
+
+    let api_error = errors::ShopApiError::Program(program_error);
+    assert_eq!(api_error.status_code(), StatusCode::CONFLICT);
+    assert_eq!(api_error.code(), "GOOD_ALREADY_EXISTS");
+}
+
+#[test]
+fn test_transaction_error_decoding() {
+    use anchor_client::solana_sdk::instruction::InstructionError;
+    use anchor_client::solana_sdk::transaction::TransactionError;
+
+    let logs = vec![
+        "Program log: AnchorError thrown in programs/shop-manager/src/lib.rs:48. Error Code: GoodNotFound. Error Number: 6001. Error Message: No good with this id.".to_string(),
+    ];
+    let transaction_error = TransactionError::InstructionError(0, InstructionError::Custom(6001));
+    let error_body =
+        errors::decode_transaction_error(&transaction_error, logs).to_response_body();
+    assert_eq!(error_body.status, 404);
+    assert_eq!(error_body.code, "GOOD_NOT_FOUND");
+    assert_eq!(error_body.details["program_error_code"], 6001);
+
+    // failures seen while waiting for a commitment come without logs
+    let transaction_error = TransactionError::InstructionError(0, InstructionError::Custom(6000));
+    let error_body =
+        errors::decode_transaction_error(&transaction_error, Vec::new()).to_response_body();
+    assert_eq!(error_body.status, 409);
+    assert_eq!(error_body.code, "GOOD_ALREADY_EXISTS");
+
+    let error_body =
+        errors::decode_transaction_error(&TransactionError::InsufficientFundsForFee, Vec::new())
+            .to_response_body();
+    assert_eq!(error_body.code, "INSUFFICIENT_FUNDS");
+}
+
+#[actix_web::test]
+async fn test_request_commitment() {
+    use actix_web::FromRequest;
+
+    let req = test::TestRequest::get()
+        .uri("/goods?commitment=finalized")
+        .insert_header((routes::COMMITMENT_HEADER, "processed"))
+        .to_http_request();
+    let commitment = routes::RequestCommitment::extract(&req).await.unwrap();
+    assert_eq!(commitment.name(), "finalized");
+
+    let req = test::TestRequest::get()
+        .uri("/goods")
+        .insert_header((routes::COMMITMENT_HEADER, "Confirmed"))
+        .to_http_request();
+    let commitment = routes::RequestCommitment::extract(&req).await.unwrap();
+    assert_eq!(commitment.name(), "confirmed");
+
+    let req = test::TestRequest::get()
+        .uri("/goods?commitment=recent")
+        .to_http_request();
+    let error = routes::RequestCommitment::extract(&req).await.err().unwrap();
+    assert_eq!(error.as_response_error().status_code(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn test_program_id_validation() {
+    let program_id = Pubkey::new_unique();
+    assert_eq!(
+        shop_solana_utils::try_get_program_id(&program_id.to_string()).unwrap(),
+        program_id
+    );
+    // valid base58 of the wrong length must not panic
+    assert!(shop_solana_utils::try_get_program_id("8agPo1zq2Zv").is_err());
+    assert!(shop_solana_utils::try_get_program_id("not base58 0OIl").is_err());
+}
+
+#[test]
+fn test_cluster_configurations() {
+    let cluster = shop_anchor_utils::try_parse_cluster("mainnet-beta", "", "").unwrap();
+    assert_eq!(cluster.url(), Cluster::Mainnet.url());
+    let cluster = shop_anchor_utils::try_parse_cluster("Testnet", "", "").unwrap();
+    assert_eq!(cluster.url(), Cluster::Testnet.url());
+
+    let cluster =
+        shop_anchor_utils::try_parse_cluster("custom", "http://10.0.0.5:8899", "").unwrap();
+    assert_eq!(cluster.url(), "http://10.0.0.5:8899");
+    assert_eq!(cluster.ws_url(), "ws://10.0.0.5:8900");
+    let cluster =
+        shop_anchor_utils::try_parse_cluster("custom", "https://rpc.example.com/key", "").unwrap();
+    assert_eq!(cluster.ws_url(), "wss://rpc.example.com/key");
+    let cluster = shop_anchor_utils::try_parse_cluster(
+        "custom",
+        "https://rpc.example.com",
+        "wss://ws.example.com",
+    )
+    .unwrap();
+    assert_eq!(cluster.ws_url(), "wss://ws.example.com");
+
+    assert!(shop_anchor_utils::try_parse_cluster("devnett", "", "").is_err());
+    assert!(shop_anchor_utils::try_parse_cluster("custom", "", "").is_err());
+    assert!(shop_anchor_utils::try_parse_cluster("custom", "rpc.example.com", "").is_err());
+    assert!(
+        shop_anchor_utils::try_parse_cluster("custom", "http://localhost:8899", "http://x")
+            .is_err()
+    );
+}
+
+#[test]
+fn test_configuration_file_formats() {
+    let toml_file = configure::parse_configuration_file(
+        "shop.toml",
+        "host = \"0.0.0.0\"\nport = 9000\ncluster = \"devnet\"\n",
+    )
+    .unwrap();
+    assert_eq!(toml_file.get("HOST").as_deref(), Some("0.0.0.0"));
+    assert_eq!(toml_file.get("PORT").as_deref(), Some("9000"));
+    assert_eq!(toml_file.get("PROGRAM_ID"), None);
+
+    let yaml_file =
+        configure::parse_configuration_file("shop.yaml", "cluster: devnet\nport: \"9001\"\n")
+            .unwrap();
+    assert_eq!(yaml_file.get("CLUSTER").as_deref(), Some("devnet"));
+    assert_eq!(yaml_file.get("PORT").as_deref(), Some("9001"));
+
+    assert!(configure::parse_configuration_file("shop.toml", "hots = \"0.0.0.0\"").is_err());
+    assert!(configure::parse_configuration_file("shop.json", "{}").is_err());
+}
+
+#[test]
+fn test_server_configurations_validation() {
+    let configurations_file = ShopConfigurationsFile::default();
+
+    let server_configurations =
+        configure::get_server_configurations("127.0.0.1, [::1]", "8080", &configurations_file)
+            .unwrap();
+    assert_eq!(
+        server_configurations.bind_addresses,
+        vec![
+            "127.0.0.1:8080".parse().unwrap(),
+            "[::1]:8080".parse().unwrap()
+        ]
+    );
+
+    let e = configure::get_server_configurations("127.0.0.1", "80800", &configurations_file)
+        .unwrap_err();
+    assert!(e.to_string().contains("PORT `80800`"));
+}
+
+#[actix_web::test]
+async fn test_route_listing() {
+    let app = test::init_service(App::new().configure(routes::configure_routes)).await;
+
+    let req = test::TestRequest::get().uri("/").to_request();
+    let routes: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(routes.len(), 32);
+    assert_eq!(routes[0]["method"], "GET");
+    assert_eq!(routes[0]["path"], "/");
+    assert!(routes.iter().any(|route| route["method"] == "POST"
+        && route["path"] == "/goods"
+        && route["body"]["name"] == "Rice"));
+    assert!(routes.iter().any(|route| {
+        route["method"] == "DELETE" && route["path"] == "/shops/{shop_id}/goods/{id}"
+    }));
+
+    // every listed route reaches a handler, unrouted requests get an empty 404 or 405
+    for route in routes::route_descriptions() {
+        let path = route
+            .path
+            .replace("{shop_id}", &Pubkey::new_unique().to_string())
+            .replace("{id}", "1")
+            .replace("{signature}", "1");
+        let mut req = test::TestRequest::default()
+            .method(actix_web::http::Method::from_bytes(route.method.as_bytes()).unwrap())
+            .uri(&path);
+        if let Some(body) = &route.body {
+            req = req.set_json(body);
+        }
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_ne!(
+            res.status(),
+            StatusCode::METHOD_NOT_ALLOWED,
+            "{} {}",
+            route.method,
+            route.path
+        );
+        let body = test::read_body(res).await;
+        assert!(!body.is_empty(), "{} {} is not routed", route.method, route.path);
+    }
+}
+
+#[actix_web::test]
+async fn test_health_reports() {
+    let app = test::init_service(App::new().configure(routes::configure_routes)).await;
+    let req = test::TestRequest::get().uri("/health/live").to_request();
+    let health_report: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(health_report["status"], "ok");
+
+    let health_report = HealthReport::new(vec![
+        HealthCheck::passed("rpc", "solana-core 1.10.35".to_string(), serde_json::Value::Null),
+        HealthCheck::failed("payer_balance", "0 lamports".to_string(), serde_json::Value::Null),
+    ]);
+    assert!(!health_report.is_healthy());
+    assert_eq!(health_report.status, "unavailable");
+}
+
+#[test]
+fn test_program_owner_must_be_bpf_loader() {
+    use anchor_client::solana_sdk::{bpf_loader, bpf_loader_upgradeable, system_program};
+
+    assert!(shop_anchor_utils::is_bpf_loader(&bpf_loader_upgradeable::id()));
+    assert!(shop_anchor_utils::is_bpf_loader(&bpf_loader::id()));
+    assert!(!shop_anchor_utils::is_bpf_loader(&system_program::id()));
+}
+
+#[test]
+fn test_goods_query() {
+    let goods: Vec<Good> = [(1, "Rice", 150), (2, "rice cakes", 90), (3, "Beans", 120)]
+        .into_iter()
+        .map(|(id, name, price)| Good {
+            id,
+            name: name.to_string(),
+            image: String::new(),
+            price,
+        })
+        .collect();
+    let ids = |goods_page: &GoodsPage| {
+        goods_page
+            .goods
+            .iter()
+            .map(|good| good.id as u64)
+            .collect::<Vec<_>>()
+    };
+
+    let goods_page =
+        shop_anchor_utils::query_goods(goods.clone(), &GoodsQuery::default()).unwrap();
+    assert_eq!(ids(&goods_page), vec![1, 2, 3]);
+    assert_eq!(goods_page.next_offset, None);
+
+    let query = GoodsQuery {
+        name: Some("RICE".to_string()),
+        sort: Some("price".to_string()),
+        ..GoodsQuery::default()
+    };
+    let goods_page = shop_anchor_utils::query_goods(goods.clone(), &query).unwrap();
+    assert_eq!(ids(&goods_page), vec![2, 1]);
+
+    let query = GoodsQuery {
+        min_price: Some(100),
+        sort: Some("name".to_string()),
+        order: Some("desc".to_string()),
+        limit: Some(1),
+        ..GoodsQuery::default()
+    };
+    let goods_page = shop_anchor_utils::query_goods(goods.clone(), &query).unwrap();
+    assert_eq!(ids(&goods_page), vec![1]);
+    assert_eq!(goods_page.total, 2);
+    assert_eq!(goods_page.next_offset, Some(1));
+
+    let query = GoodsQuery {
+        sort: Some("colour".to_string()),
+        ..GoodsQuery::default()
+    };
+    let error = shop_anchor_utils::query_goods(goods, &query).unwrap_err();
+    assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_batch_instructions_are_packed_per_transaction() {
+    use anchor_client::solana_client::nonblocking::rpc_client::RpcClient;
+    use anchor_client::solana_sdk::message::Message;
+    use anchor_client::solana_sdk::packet::PACKET_DATA_SIZE;
+
+    let program_client = ShopProgramClient {
+        rpc: RpcClient::new(Cluster::Localnet.url().to_string()),
+        payer: Keypair::new(),
+        program_id: Pubkey::new_unique(),
+        cluster: Cluster::Localnet,
+    };
+    let goods_account = Pubkey::new_unique();
+    let instructions = (0..40)
+        .map(|id| {
+            let good = Good {
+                id,
+                name: format!("good number {id}"),
+                image: format!("https://example.com/images/{id}.png"),
+                price: 100,
+            };
+            let instruction = program_client.instruction(
+                shop_manager::accounts::AddGoods { goods_account },
+                shop_manager::instruction::InsertGoods { good },
+            );
+            (id as u64, instruction)
+        })
+        .collect();
+
+    let groups = program_client.pack_instructions(instructions);
+    assert!(groups.len() > 1);
+    let ids: Vec<u64> = groups.iter().flatten().map(|(id, _)| *id).collect();
+    assert_eq!(ids, (0..40).collect::<Vec<u64>>());
+    for group in groups {
+        let instructions: Vec<_> = group.into_iter().map(|(_, instruction)| instruction).collect();
+        let message = Message::new(&instructions, Some(&program_client.payer.pubkey()));
+        assert!(1 + 64 + message.serialize().len() <= PACKET_DATA_SIZE);
+    }
+}
+
+#[test]
+fn test_good_validation() {
+    let good = Good {
+        id: 1,
+        name: "Rice".to_string(),
+        image: "https://example.com/rice.png".to_string(),
+        price: 150,
+    };
+    assert!(shop_anchor_utils::validate_good(&good).is_ok());
+
+    let invalid_good = Good {
+        id: 0,
+        name: " ".to_string(),
+        image: "rice.png".to_string(),
+        price: 0,
+    };
+    let error = shop_anchor_utils::validate_good(&invalid_good).unwrap_err();
+    assert_eq!(error.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+    let fields: Vec<&str> = match &error {
+        errors::ShopApiError::InvalidGood(field_errors) => {
+            field_errors.iter().map(|field_error| field_error.field).collect()
+        }
+        _ => panic!("unexpected error {error:?}"),
+    };
+    assert_eq!(fields, vec!["id", "name", "image", "price"]);
+}
+
+#[test]
+fn test_goods_account_space() {
+    let good = Good {
+        id: 1,
+        name: "Rice".to_string(),
+        image: "https://example.com/rice.png".to_string(),
+        price: 150,
+    };
+    let good_size = vec![good.clone()].try_to_vec().unwrap().len();
+
+    let mut empty_space = shop_anchor_utils::GoodsAccountSpace::new(Vec::new(), 0);
+    let error = empty_space.try_put(&good).unwrap_err();
+    assert_eq!(error.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(error.details()["fields"][0]["field"], "name");
+    assert_eq!(error.details()["fields"][1]["field"], "image");
+
+    let mut space = shop_anchor_utils::GoodsAccountSpace::new(vec![good.clone()], good_size);
+    let repriced_good = Good {
+        price: 175,
+        ..good.clone()
+    };
+    assert!(space.try_put(&repriced_good).is_ok());
+    assert_eq!(space.goods(), &[repriced_good.clone()][..]);
+
+    let renamed_good = Good {
+        name: "Brown rice".to_string(),
+        ..good.clone()
+    };
+    let error = space.try_put(&renamed_good).unwrap_err();
+    assert_eq!(error.status_code(), StatusCode::INSUFFICIENT_STORAGE);
+    assert_eq!(error.code(), "GOODS_ACCOUNT_FULL");
+    assert_eq!(space.goods(), &[repriced_good.clone()][..]);
+
+    let other_good = Good { id: 2, ..good };
+    assert_eq!(
+        space.try_put(&other_good).unwrap_err().code(),
+        "GOODS_ACCOUNT_FULL"
+    );
+    assert_eq!(space.into_goods(), vec![repriced_good]);
+}
+
+#[test]
+fn test_good_etags() {
+    let good = Good {
+        id: 1,
+        name: "Rice".to_string(),
+        image: "https://example.com/rice.png".to_string(),
+        price: 150,
+    };
+    let etag = shop_anchor_utils::get_good_etag(&good);
+    assert!(!etag.weak);
+    assert_eq!(etag, shop_anchor_utils::get_good_etag(&good.clone()));
+    let repriced_good = Good {
+        price: 175,
+        ..good.clone()
+    };
+    assert_ne!(etag, shop_anchor_utils::get_good_etag(&repriced_good));
+
+    let if_match = |value: String| {
+        test::TestRequest::default()
+            .insert_header((header::IF_MATCH, value))
+            .to_http_request()
+    };
+    assert!(routes::check_if_match(&test::TestRequest::default().to_http_request(), &etag).is_ok());
+    assert!(routes::check_if_match(&if_match("*".to_string()), &etag).is_ok());
+    assert!(routes::check_if_match(&if_match(format!("\"other\", {etag}")), &etag).is_ok());
+    // weak tags never match when guarding a write
+    assert!(routes::check_if_match(&if_match(format!("W/{etag}")), &etag).is_err());
+    let error = routes::check_if_match(
+        &if_match(shop_anchor_utils::get_good_etag(&repriced_good).to_string()),
+        &etag,
+    )
+    .unwrap_err();
+    assert_eq!(error.status_code(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(error.details()["etag"], etag.to_string());
+}
+
+#[actix_web::test]
+async fn test_idempotency_key() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let handler_calls = calls.clone();
+    let store = Arc::new(IdempotencyStore::new(Duration::from_secs(60)));
+    let app = test::init_service(App::new().wrap(Idempotency::new(store)).route(
+        "/goods",
+        web::post().to(move |body: web::Bytes| {
+            let calls = handler_calls.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { HttpResponse::Created().body(format!("{calls}:{}", from_utf8(&body).unwrap())) }
+        }),
+    ))
+    .await;
+    let post = |key: &str, body: &'static str| {
+        test::TestRequest::post()
+            .uri("/goods")
+            .insert_header((IDEMPOTENCY_KEY_HEADER, key.to_string()))
+            .set_payload(body)
+            .to_request()
+    };
+
+    let res = test::call_service(&app, post("first", "rice")).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    assert!(res.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+    assert_eq!(test::read_body(res).await, "1:rice");
+
+    let res = test::call_service(&app, post("first", "rice")).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    assert_eq!(res.headers().get(IDEMPOTENT_REPLAYED_HEADER).unwrap(), "true");
+    assert_eq!(test::read_body(res).await, "1:rice");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    let res = test::call_service(&app, post("first", "salt")).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let res = test::call_service(&app, post("second", "salt")).await;
+    assert_eq!(test::read_body(res).await, "2:salt");
+    let res = test::call_service(&app, post("", "salt")).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    // a request failing after it sent a transaction is not run again
+    let signature = anchor_client::solana_sdk::signature::Signature::new(&[7; 64]);
+    let failed_calls = Arc::new(AtomicUsize::new(0));
+    let handler_calls = failed_calls.clone();
+    let store = Arc::new(IdempotencyStore::new(Duration::from_secs(60)));
+    let app = test::init_service(App::new().wrap(Idempotency::new(store)).route(
+        "/goods",
+        web::post().to(move || {
+            handler_calls.fetch_add(1, Ordering::SeqCst);
+            idempotency::record_submitted_transaction(&signature);
+            async { HttpResponse::GatewayTimeout().finish() }
+        }),
+    ))
+    .await;
+    let res = test::call_service(&app, post("third", "rice")).await;
+    assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+    let res = test::call_service(&app, post("third", "rice")).await;
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    let submitted: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(submitted["status"], 202);
+    assert_eq!(submitted["code"], "TRANSACTIONS_SUBMITTED");
+    assert_eq!(submitted["details"]["signatures"][0], signature.to_string());
+    assert_eq!(failed_calls.load(Ordering::SeqCst), 1);
+}
+
+#[actix_web::test]
+async fn test_api_keys() {
+    use std::sync::Arc;
+
+    let api_keys_file = std::env::temp_dir().join("shop_manager_api_test_api_keys.json");
+    let api_keys_file = api_keys_file.to_str().unwrap();
+    let _ = std::fs::remove_file(api_keys_file);
+
+    let (api_key_record, api_key) = api_keys::create_api_key(api_keys_file, "till 1").unwrap();
+    assert!(api_keys::create_api_key(api_keys_file, "till 1").is_err());
+    let stored_api_keys = std::fs::read_to_string(api_keys_file).unwrap();
+    assert!(!stored_api_keys.contains(&api_key));
+    assert!(stored_api_keys.contains(&api_key_record.key_hash));
+
+    // looks at the file on every request so the revocation below applies at once
+    let store =
+        Arc::new(ApiKeyStore::new(api_keys_file.to_string()).with_reload_interval(Duration::ZERO));
+    let app = test::init_service(
+        App::new()
+            .wrap(ApiKeyAuth::new(store))
+            .route(
+                "/label",
+                web::get().to(|| async { api_keys::current_api_key_label().unwrap_or_default() }),
+            )
+            .service(routes::health_scope()),
+    )
+    .await;
+    let get_label = |header: Option<(&'static str, String)>| {
+        let mut req = test::TestRequest::get().uri("/label");
+        if let Some(header) = header {
+            req = req.insert_header(header);
+        }
+        req.to_request()
+    };
+
+    let res = test::call_service(&app, get_label(None)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = test::call_service(&app, get_label(Some((API_KEY_HEADER, api_key.clone())))).await;
+    assert_eq!(test::read_body(res).await, "till 1");
+    let bearer = Some(("authorization", format!("Bearer {api_key}")));
+    let res = test::call_service(&app, get_label(bearer.clone())).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let wrong_key = Some((API_KEY_HEADER, format!("{api_key}x")));
+    let res = test::call_service(&app, get_label(wrong_key)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let req = test::TestRequest::get().uri("/health/live").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    // idempotency keys of different api keys never meet
+    let (_, other_api_key) = api_keys::create_api_key(api_keys_file, "till 2").unwrap();
+    let store = Arc::new(ApiKeyStore::new(api_keys_file.to_string()));
+    let idempotency_store = Arc::new(IdempotencyStore::new(Duration::from_secs(60)));
+    let idempotent_app = test::init_service(
+        App::new()
+            .wrap(Idempotency::new(idempotency_store))
+            .wrap(ApiKeyAuth::new(store))
+            .route(
+                "/label",
+                web::post().to(|| async { api_keys::current_api_key_label().unwrap_or_default() }),
+            ),
+    )
+    .await;
+    for (api_key, label) in [(&api_key, "till 1"), (&other_api_key, "till 2")] {
+        let req = test::TestRequest::post()
+            .uri("/label")
+            .insert_header((API_KEY_HEADER, api_key.clone()))
+            .insert_header((IDEMPOTENCY_KEY_HEADER, "order-1"))
+            .to_request();
+        let res = test::call_service(&idempotent_app, req).await;
+        assert!(res.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+        assert_eq!(test::read_body(res).await, label);
+    }
+
+    api_keys::revoke_api_key(api_keys_file, &api_key_record.id).unwrap();
+    let res = test::call_service(&app, get_label(bearer)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let error: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(error["code"], "UNAUTHORIZED");
+
+    // the default store only looks at the file again after its reload interval
+    let req = test::TestRequest::post()
+        .uri("/label")
+        .insert_header((API_KEY_HEADER, api_key.clone()))
+        .insert_header((IDEMPOTENCY_KEY_HEADER, "order-2"))
+        .to_request();
+    assert_eq!(test::call_service(&idempotent_app, req).await.status(), StatusCode::OK);
+
+    std::fs::remove_file(api_keys_file).unwrap();
+}
+
+#[test]
+fn test_key_pair_formats() {
+    let key_pair = Keypair::new();
+    let bytes = key_pair.to_bytes();
+
+    let comma_separated = bytes.map(|b| b.to_string()).join(",");
+    let json_array = format!("[{comma_separated}]");
+    let base58 = bytes.to_base58();
+    for key_pair_string in [comma_separated, json_array, base58] {
+        let parsed = shop_solana_utils::try_parse_key_pair(&key_pair_string).unwrap();
+        assert_eq!(parsed.to_bytes(), bytes);
+    }
+
+    if let Ok(home) = std::env::var("HOME") {
+        assert_eq!(
+            shop_solana_utils::expand_home_dir("~/.config/solana/id.json"),
+            format!("{}/.config/solana/id.json", home.trim_end_matches('/'))
+        );
+    }
+    assert_eq!(shop_solana_utils::expand_home_dir("id.json"), "id.json");
+    let error = shop_solana_utils::try_parse_key_pair("~/.config/solana/idd.json").unwrap_err();
+    assert!(error.to_string().contains("keypair file not found"));
+
+    // a public key is not a keypair
+    assert!(shop_solana_utils::try_parse_key_pair(&key_pair.pubkey().to_string()).is_err());
+
+    assert!(shop_solana_utils::get_key_pair_bytes("PAYER_KEY_PAIR", None, false).is_err());
+    assert!(shop_solana_utils::get_key_pair_bytes("PAYER_KEY_PAIR", None, true).is_ok());
+    assert!(shop_solana_utils::get_key_pair_bytes("PAYER_KEY_PAIR", Some("1,2,3"), true).is_err());
+}
+
+#[test]
+fn test_persisted_shop_state_round_trip() {
+    let state_file = std::env::temp_dir().join("shop_manager_api_test_state.json");
+    let state_file = state_file.to_str().unwrap();
+    let _ = std::fs::remove_file(state_file);
+    assert!(configure::load_persisted_shop_state(state_file)
+        .unwrap()
+        .is_none());
+
+    let key_pair = Keypair::new();
+    let persisted_shop_state = PersistedShopState {
+        goods_account: key_pair.pubkey().to_string(),
+        goods_account_key_pair: key_pair.to_bytes().to_vec(),
+        initialize_signature: Some("signature".to_string()),
+        name: None,
+    };
+    configure::save_persisted_shop_state(state_file, &persisted_shop_state).unwrap();
+
+    let loaded = configure::load_persisted_shop_state(state_file)
+        .unwrap()
+        .unwrap();
+    assert_eq!(loaded.goods_account, key_pair.pubkey().to_string());
+    assert_eq!(loaded.key_pair_bytes().unwrap(), key_pair.to_bytes());
+
+    std::fs::remove_file(state_file).unwrap();
+}
+
+#[actix_web::test]
+async fn test_shops() {
+    let shops_file = std::env::temp_dir().join("shop_manager_api_test_shops.json");
+    let shops_file = shops_file.to_str().unwrap();
+    let _ = std::fs::remove_file(shops_file);
+
+    let key_pair = Keypair::new();
+    let goods_account = key_pair.pubkey().to_string();
+    let shop_registry = configure::load_shop_registry(shops_file).unwrap();
+    assert!(shop_registry.summaries().is_empty());
+    shop_registry
+        .add(PersistedShopState {
+            goods_account: goods_account.clone(),
+            goods_account_key_pair: key_pair.to_bytes().to_vec(),
+            initialize_signature: Some("signature".to_string()),
+            name: Some("Corner shop".to_string()),
+        })
+        .unwrap();
+    let shop_registry = configure::load_shop_registry(shops_file).unwrap();
+    assert!(shop_registry.contains(&goods_account));
+
+    // a shop is only served once its account is initialized
+    let pending_key_pair = Keypair::new();
+    let pending_goods_account = pending_key_pair.pubkey().to_string();
+    shop_registry
+        .add(PersistedShopState {
+            goods_account: pending_goods_account.clone(),
+            goods_account_key_pair: pending_key_pair.to_bytes().to_vec(),
+            initialize_signature: None,
+            name: None,
+        })
+        .unwrap();
+    assert!(!shop_registry.contains(&pending_goods_account));
+    assert_eq!(shop_registry.summaries().len(), 1);
+    assert_eq!(shop_registry.pending_goods_accounts(), vec![pending_goods_account.clone()]);
+    shop_registry
+        .mark_initialized(&pending_goods_account, "signature")
+        .unwrap();
+    let shop_registry = configure::load_shop_registry(shops_file).unwrap();
+    assert!(shop_registry.contains(&pending_goods_account));
+    std::fs::remove_file(shops_file).unwrap();
+
+    let shop_state = test_utils::offline_shop_state();
+    let default_goods_account = shop_state.goods_account_key_pair.pubkey().to_string();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(shop_state))
+            .app_data(web::Data::new(shop_registry))
+            .configure(routes::configure_routes),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/shops").to_request();
+    let shops: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(shops[0]["shop_id"], default_goods_account);
+    assert_eq!(shops[0]["default"], true);
+    assert_eq!(shops[1]["shop_id"], goods_account);
+    assert_eq!(shops[1]["name"], "Corner shop");
+
+    let unknown_shop = Keypair::new().pubkey();
+    for uri in [
+        format!("/shops/{unknown_shop}"),
+        format!("/shops/{unknown_shop}/goods/1"),
+        "/shops/not-a-shop/goods".to_string(),
+    ] {
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND, "{uri}");
+        let error: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(error["code"], "SHOP_NOT_FOUND");
+    }
+}
+
+#[actix_web::test]
+async fn test_shop_goods() {
+    let (mut solana_test_validator, shop_state) = init_test_service().await;
+    let shops_file = std::env::temp_dir().join("shop_manager_api_test_shop_goods.json");
+    let shops_file = shops_file.to_str().unwrap();
+    let _ = std::fs::remove_file(shops_file);
+    let shop_registry = configure::load_shop_registry(shops_file).unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(shop_state))
+            .app_data(web::Data::new(shop_registry))
+            .configure(routes::configure_routes),
+    )
+    .await;
+
+    let req = test::TestRequest::post().uri("/shops").to_request();
+    let shop_created: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let shop_id = shop_created["shop"]["shop_id"].as_str().unwrap().to_string();
+
+    let good = Good {
+        name: "rice".to_string(),
+        image: "https://example.com/image1.png".to_string(),
+        id: 1,
+        price: 150,
+    };
+    let req = test::TestRequest::post()
+        .uri(&format!("/shops/{shop_id}/goods"))
+        .set_json(&good)
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let location = res.headers().get(header::LOCATION).unwrap().to_str().unwrap();
+    assert_eq!(location, format!("/shops/{shop_id}/goods/1"));
+
+    let req = test::TestRequest::get().uri(&format!("/shops/{shop_id}/goods/1")).to_request();
+    let shop_good: Good = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(shop_good, good);
+
+    std::fs::remove_file(shops_file).unwrap();
+    tear_down(&mut solana_test_validator)
+}
+
+#[actix_web::test]
+async fn test_airdrop_validation() {
+    let shop_state = test_utils::offline_shop_state();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(shop_state))
+            .configure(routes::configure_routes),
+    )
+    .await;
+
+    // refused before the faucet is asked
+    for body in [
+        serde_json::json!({ "sol": 0.0 }),
+        serde_json::json!({ "sol": -1.0 }),
+        serde_json::json!({ "sol": shop_solana_utils::MAX_AIRDROP_SOL + 1.0 }),
+        serde_json::json!({ "sol": "1.5" }),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/wallet/airdrop")
+            .set_json(body)
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let error: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(error["status"], 400);
+        assert_eq!(error["code"], "VALIDATION_ERROR");
+    }
+}
+
+#[actix_web::test]
+async fn test_shop_creation_validation() {
+    let shops_file = std::env::temp_dir().join("shop_manager_api_test_new_shops.json");
+    let shops_file = shops_file.to_str().unwrap();
+    let _ = std::fs::remove_file(shops_file);
+    let shop_registry = configure::load_shop_registry(shops_file).unwrap();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(test_utils::offline_shop_state()))
+            .app_data(web::Data::new(shop_registry))
+            .configure(routes::configure_routes),
+    )
+    .await;
+
+    // refused before any keypair is generated, the name is counted in characters
+    let long_name = "é".repeat(routes::MAX_SHOP_NAME_LENGTH + 1);
+    for body in [
+        serde_json::json!({ "name": 5 }),
+        serde_json::json!({ "name": " " }),
+        serde_json::json!({ "name": long_name }),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/shops")
+            .set_json(body)
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let error: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(error["code"], "VALIDATION_ERROR");
+    }
+    assert!(!std::path::Path::new(shops_file).exists());
+}
+
+#[test]
+fn test_only_on_chain_failures_are_retried() {
+    use anchor_client::solana_sdk::instruction::InstructionError;
+    use anchor_client::solana_sdk::signature::Signature;
+    use anchor_client::solana_sdk::transaction::TransactionError;
+
+    let transaction_error = TransactionError::InstructionError(0, InstructionError::Custom(6000));
+    let refused = shop_anchor_utils::TransactionFailure {
+        signature: Some(Signature::default()),
+        error: errors::decode_transaction_error(&transaction_error, Vec::new()),
+    };
+    assert!(refused.is_on_chain());
+    let timed_out = shop_anchor_utils::TransactionFailure {
+        signature: Some(Signature::default()),
+        error: errors::ShopApiError::Rpc("timed out".to_string()),
+    };
+    assert!(!timed_out.is_on_chain());
+}
+
+#[test]
+fn test_explorer_urls() {
+    assert_eq!(
+        shop_solana_utils::get_explorer_url("sig", &Cluster::Devnet),
+        "https://explorer.solana.com/tx/sig?cluster=devnet"
+    );
+    assert_eq!(
+        shop_solana_utils::get_explorer_url("sig", &Cluster::Mainnet),
+        "https://explorer.solana.com/tx/sig"
+    );
+    assert_eq!(
+        shop_solana_utils::get_explorer_url("sig", &Cluster::Localnet),
+        "https://explorer.solana.com/tx/sig?cluster=custom&customUrl=http%3A%2F%2F127.0.0.1%3A8899"
+    );
+}
+
 mod test_utils {
     use super::*;
     use std::process::Command;
@@ -133,13 +1111,7 @@ mod test_utils {
             static ref SHOP_CONFIGURATIONS: ShopConfigurations =
                 configure::setup_environment_and_get_configurations().unwrap();
         }
-        let tx_id = tokio::task::spawn_blocking(|| -> Result<String, errors::ShopCustomError> {
-            let tx_id = shop_solana_utils::request_airdrop_for_current_wallet(&SHOP_CONFIGURATIONS)
-                .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
-            std::thread::sleep(Duration::from_millis(500));
-            Ok(tx_id)
-        })
-        .await??;
+        let tx_id = request_airdrop(&SHOP_CONFIGURATIONS).await?;
         // info!("tx_id:{}", tx_id);
         let shop_state = configure::get_shop_state(&SHOP_CONFIGURATIONS)?;
 
@@ -158,16 +1130,67 @@ mod test_utils {
         Ok(shop_state)
     }
 
-    pub async fn airdrop_to_current_wallet(shop_configurations: &'static ShopConfigurations) {
-        let tx_id = tokio::task::spawn_blocking(|| -> Result<String, errors::ShopCustomError> {
-            let tx_id = shop_solana_utils::request_airdrop_for_current_wallet(shop_configurations)
-                .map_err(|e| errors::ShopCustomError::getCustomError(e))?;
-            info!("airdrop_to_current_wallet: tx_id:{}", tx_id);
+    /// State with random keypairs that never reaches a cluster, for the tests
+    /// that do not need a payer or the process environment.
+    pub fn offline_shop_state() -> ShopState<'static> {
+        let shop_configurations = ShopConfigurations {
+            host: "127.0.0.1".to_string(),
+            port: "8080".to_string(),
+            server: ShopServerConfigurations {
+                bind_addresses: Vec::new(),
+                unix_sockets: Vec::new(),
+                workers: None,
+                backlog: None,
+                keep_alive_secs: None,
+                client_request_timeout_ms: None,
+                client_disconnect_timeout_ms: None,
+            },
+            funding_policy: FundingPolicy {
+                airdrop_enabled: false,
+                min_balance_lamports: LAMPORTS_PER_SOL,
+                airdrop_lamports: LAMPORTS_PER_SOL,
+                airdrop_max_retries: 0,
+                airdrop_backoff_ms: 0,
+            },
+            program_id: Pubkey::new_unique().to_string(),
+            cluster: "localnet".to_string(),
+            cluster_url: String::new(),
+            cluster_ws_url: String::new(),
+            commitment: CommitmentConfig::confirmed(),
+            payer_key_pair_bytes: Keypair::new().to_bytes(),
+            account_key_pair_bytes: Keypair::new().to_bytes(),
+            state_file: None,
+            shops_file: String::new(),
+            api_keys_file: String::new(),
+            api_auth_enabled: false,
+            health_min_balance_lamports: 0,
+            idempotency_ttl_secs: 60,
+        };
+        let shop_configurations: &'static ShopConfigurations =
+            Box::leak(Box::new(shop_configurations));
+        configure::get_shop_state(shop_configurations).unwrap()
+    }
 
-            std::thread::sleep(Duration::from_millis(500));
+    pub async fn airdrop_to_current_wallet(shop_configurations: &'static ShopConfigurations) {
+        match request_airdrop(shop_configurations).await {
+            Ok(tx_id) => info!("airdrop_to_current_wallet: tx_id:{}", tx_id),
+            Err(e) => error!("airdrop_to_current_wallet: {e}"),
+        }
+    }
 
-            Ok(tx_id)
-        })
-        .await;
+    /// The tests run against a local validator so the payer is always airdropped,
+    /// whatever the configured funding policy is.
+    async fn request_airdrop(
+        shop_configurations: &ShopConfigurations,
+    ) -> Result<String, errors::ShopApiError> {
+        let program_client = shop_anchor_utils::ShopProgramClient::new(shop_configurations)?;
+        let funding_policy = &shop_configurations.funding_policy;
+        let tx_id = shop_solana_utils::request_airdrop_with_retries(
+            &program_client,
+            10 * LAMPORTS_PER_SOL,
+            funding_policy,
+        )
+        .await?;
+        Ok(tx_id.to_string())
     }
 }